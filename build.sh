@@ -7,9 +7,40 @@ cargo build --release
 # 2. 複製執行檔到 mkfs 的來源目錄
 # 注意：你的目錄結構可能不同，請根據實際情況調整 ../
 cp target/riscv64gc-unknown-none-elf/release/program ../mkfs/fs_root/program.elf
+cp target/riscv64gc-unknown-none-elf/release/cow ../mkfs/fs_root/cow
+cp target/riscv64gc-unknown-none-elf/release/top ../mkfs/fs_root/top
+cp target/riscv64gc-unknown-none-elf/release/fp ../mkfs/fs_root/fp
+cp target/riscv64gc-unknown-none-elf/release/threads ../mkfs/fs_root/threads
+cp target/riscv64gc-unknown-none-elf/release/exec ../mkfs/fs_root/exec
+cp target/riscv64gc-unknown-none-elf/release/env ../mkfs/fs_root/env
+cp target/riscv64gc-unknown-none-elf/release/wx ../mkfs/fs_root/wx
+cp target/riscv64gc-unknown-none-elf/release/aslr ../mkfs/fs_root/aslr
+cp target/riscv64gc-unknown-none-elf/release/stack ../mkfs/fs_root/stack
+cp target/riscv64gc-unknown-none-elf/release/heap ../mkfs/fs_root/heap
+cp target/riscv64gc-unknown-none-elf/release/malloc ../mkfs/fs_root/malloc
+# 動態載入器與共用函式庫
+mkdir -p ../mkfs/fs_root/lib
+cp target/riscv64gc-unknown-none-elf/release/ld ../mkfs/fs_root/lib/ld.so
+cp target/riscv64gc-unknown-none-elf/release/libulib ../mkfs/fs_root/lib/libulib.so
+
+# 動態連結的程式 (連結到上面編出的 libulib.so)
+cd ../user_dyn
+cargo clean
+cargo build --release
+# 確認每個程式都真的動態連結：需要 libulib.so (DT_NEEDED)，ulib_ 符號都是未定義的 (由 ld.so 解析)
+for bin in ls cat pid dynlink; do
+    elf=target/riscv64gc-unknown-none-elf/release/$bin
+    if ! readelf -d $elf | grep -q 'NEEDED.*\[libulib.so\]' \
+        || ! readelf --dyn-syms -W $elf | grep -q ' UND ulib_' \
+        || readelf --syms -W $elf | grep -v ' UND ' | grep -q ' ulib_'; then
+        echo "$bin is not dynamically linked against libulib.so"
+        exit 1
+    fi
+done
 cp target/riscv64gc-unknown-none-elf/release/ls ../mkfs/fs_root/ls
 cp target/riscv64gc-unknown-none-elf/release/cat ../mkfs/fs_root/cat
-cp target/riscv64gc-unknown-none-elf/release/pid ../mkfs/fs_root/pid 
+cp target/riscv64gc-unknown-none-elf/release/pid ../mkfs/fs_root/pid
+cp target/riscv64gc-unknown-none-elf/release/dynlink ../mkfs/fs_root/dynlink
 
 # 3. 重新打包磁碟
 cd ../mkfs
@@ -5,12 +5,14 @@ cargo build
 
 # 執行 QEMU
 # 新增參數：
+# -smp 4 (4 個 hart，hart 0 開機後以 SBI HSM 放行其他 hart)
 # -drive file=disk.img,if=none,format=raw,id=x0 
 # -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0
 
 qemu-system-riscv64 \
     -machine virt \
     -nographic \
+    -smp 4 \
     -bios none \
     -kernel target/riscv64gc-unknown-none-elf/debug/eos1 \
     -drive file=disk.img,if=none,format=raw,id=x0 \
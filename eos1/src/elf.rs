@@ -1,7 +1,10 @@
 use core::mem::size_of;
 // [修正] 引入 PageTable 結構
-use crate::mm::page_table::{map, translate, PageTable, PTE_R, PTE_W, PTE_X, PTE_U};
-use crate::mm::frame::alloc_frame;
+use crate::mm::page_table::{translate, try_map, PageTable, PTE_R, PTE_W, PTE_X, PTE_U, KERNEL_BASE, KERNEL_GIGAPAGE_END, USER_SPACE_END};
+use crate::mm::frame::{self, alloc_frame};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -41,65 +44,328 @@ pub struct ProgramHeader {
     pub align: u64,
 }
 
-/// 解析 ELF 並載入到指定的 Page Table 中
-pub unsafe fn load_elf(data: &[u8], page_table: &mut PageTable) -> Option<u64> {
-    if data.len() < size_of::<ElfHeader>() { return None; }
-    let header = unsafe { &*(data.as_ptr() as *const ElfHeader) };
+pub const ELF_MAGIC: [u8; 4] = [0x7f, 0x45, 0x4c, 0x46];
+pub const ELFCLASS64: u8 = 2;
+pub const ELFDATA2LSB: u8 = 1;
+pub const EV_CURRENT: u8 = 1;
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+pub const EM_RISCV: u16 = 0xF3;
 
-    if header.magic != [0x7f, 0x45, 0x4c, 0x46] || header.machine != 0xF3 {
-        return None;
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_INTERP: u32 = 3;
+pub const PT_PHDR: u32 = 6;
+pub const PT_GNU_STACK: u32 = 0x6474_e551;
+
+// Program Header 的 flags
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+// Dynamic Section 的 tag 與 RISC-V 的 Relocation 類型
+pub const DT_NULL: i64 = 0;
+pub const DT_RELA: i64 = 7;
+pub const DT_RELASZ: i64 = 8;
+pub const DT_RELAENT: i64 = 9;
+pub const R_RISCV_NONE: u32 = 0;
+pub const R_RISCV_RELATIVE: u32 = 3;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Dyn {
+    pub tag: i64,
+    pub val: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Rela {
+    pub offset: u64,
+    pub info: u64,
+    pub addend: i64,
+}
+
+/// 載入 ELF 失敗的原因 (SPAWN / EXECVE 會轉成各自的錯誤碼，見 exec::ExecError)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// 檔案比 ELF Header 或 Program Header Table 短
+    Truncated,
+    BadMagic,
+    /// 不是 64 位元 (ELFCLASS64)
+    BadClass,
+    /// 不是 Little Endian
+    BadEndian,
+    BadVersion,
+    /// 不是可執行檔 (ET_EXEC 或 PIE 的 ET_DYN)
+    BadType,
+    /// 不是 RISC-V 的執行檔
+    BadMachine,
+    /// Program Header 的大小與 ProgramHeader 不同
+    BadProgramHeader,
+    /// Segment 的內容超出檔案、filesz > memsz，或位址超出使用者空間 (包含與核心重疊)
+    BadSegment,
+    /// 檔案位移與虛擬位址的對齊方式不一致
+    MisalignedSegment,
+    /// 兩個 Segment 使用到同一頁 (或蓋到使用者堆疊、已經載入的其他映像檔)
+    OverlappingSegments,
+    /// 同時可寫又可執行 (W^X)，或沒有任何權限
+    BadPermissions,
+    /// 沒有任何 LOAD Segment
+    NoSegments,
+    /// entry 不在可執行的 Segment 中
+    BadEntry,
+    OutOfMemory,
+    /// Dynamic Section 或 Relocation Table 超出檔案範圍
+    BadDynamic,
+    /// 不支援的 Relocation 類型 (目前只處理 R_RISCV_RELATIVE)，或目標不在 Segment 中
+    BadRelocation,
+    /// PT_INTERP 超出檔案或不是合法的路徑，或是動態載入器本身也需要載入器
+    BadInterpreter,
+}
+
+impl ElfError {
+    /// 依照宣告順序排列 (錯誤碼與 exec_errors.rs 中說明的順序由這個順序決定)
+    pub const ALL: [ElfError; 18] = [
+        ElfError::Truncated, ElfError::BadMagic, ElfError::BadClass, ElfError::BadEndian, ElfError::BadVersion,
+        ElfError::BadType, ElfError::BadMachine, ElfError::BadProgramHeader, ElfError::BadSegment,
+        ElfError::MisalignedSegment, ElfError::OverlappingSegments, ElfError::BadPermissions,
+        ElfError::NoSegments, ElfError::BadEntry, ElfError::OutOfMemory, ElfError::BadDynamic,
+        ElfError::BadRelocation, ElfError::BadInterpreter,
+    ];
+}
+
+/// 載入結果：entry 與 Program Header Table 在使用者空間的位置 (給 auxv 的 AT_PHDR / AT_PHENT / AT_PHNUM)，
+/// bias 是 PIE 被搬動的距離 (動態載入器的 bias 就是 AT_BASE)，end 是最後一個 Segment 結束的頁面邊界
+pub struct LoadedElf {
+    pub entry: u64,
+    pub bias: u64,
+    pub end: usize,
+    pub phdr: Option<u64>,
+    pub phent: u16,
+    pub phnum: u16,
+}
+
+/// 從 data[offset..] 讀出一個 T (檔案中的資料不一定對齊)
+fn read_struct<T: Copy>(data: &[u8], offset: u64) -> Result<T, ElfError> {
+    let start = usize::try_from(offset).map_err(|_| ElfError::Truncated)?;
+    let bytes = data.get(start..).and_then(|rest| rest.get(..size_of::<T>())).ok_or(ElfError::Truncated)?;
+    Ok(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+/// 檢查 ELF Header
+pub fn parse_header(data: &[u8]) -> Result<ElfHeader, ElfError> {
+    let header: ElfHeader = read_struct(data, 0)?;
+    if header.magic != ELF_MAGIC { return Err(ElfError::BadMagic); }
+    if header.class != ELFCLASS64 { return Err(ElfError::BadClass); }
+    if header.endian != ELFDATA2LSB { return Err(ElfError::BadEndian); }
+    if header.version != EV_CURRENT || header.version2 != EV_CURRENT as u32 { return Err(ElfError::BadVersion); }
+    if header.type_ != ET_EXEC && header.type_ != ET_DYN { return Err(ElfError::BadType); }
+    if header.machine != EM_RISCV { return Err(ElfError::BadMachine); }
+    if header.phnum > 0 && header.phentsize as usize != size_of::<ProgramHeader>() { return Err(ElfError::BadProgramHeader); }
+    Ok(header)
+}
+
+/// 讀出整個 Program Header Table (每一項都在檔案範圍內)
+pub fn program_headers(data: &[u8], header: &ElfHeader) -> Result<Vec<ProgramHeader>, ElfError> {
+    (0..header.phnum as u64).map(|i| {
+        let offset = header.phoff.checked_add(i * size_of::<ProgramHeader>() as u64).ok_or(ElfError::Truncated)?;
+        read_struct(data, offset)
+    }).collect()
+}
+
+/// 動態連結的執行檔要求的動態載入器路徑 (PT_INTERP)，靜態連結的執行檔回傳 None
+pub fn interpreter(data: &[u8]) -> Result<Option<String>, ElfError> {
+    let header = parse_header(data)?;
+    let Some(interp) = program_headers(data, &header)?.into_iter().find(|ph| ph.type_ == PT_INTERP) else { return Ok(None) };
+    let start = usize::try_from(interp.offset).map_err(|_| ElfError::BadInterpreter)?;
+    let len = usize::try_from(interp.filesz).map_err(|_| ElfError::BadInterpreter)?;
+    let bytes = data.get(start..).and_then(|rest| rest.get(..len)).ok_or(ElfError::BadInterpreter)?;
+    // 路徑以 0 結尾
+    let path = bytes.split(|&b| b == 0).next().unwrap_or(&[]);
+    match core::str::from_utf8(path) {
+        Ok(path) if !path.is_empty() => Ok(Some(String::from(path))),
+        _ => Err(ElfError::BadInterpreter),
+    }
+}
+
+/// 執行檔要求的堆疊大小 (PT_GNU_STACK 的 memsz，連結時以 -z stack-size 設定)，沒有指定時回傳 None
+pub fn stack_size(data: &[u8]) -> Result<Option<u64>, ElfError> {
+    let header = parse_header(data)?;
+    Ok(program_headers(data, &header)?.into_iter().find(|ph| ph.type_ == PT_GNU_STACK && ph.memsz > 0).map(|ph| ph.memsz))
+}
+
+/// 檢查一個 LOAD Segment：內容在檔案中、位址在使用者空間中且不碰到核心、對齊與權限正確
+fn check_segment(data: &[u8], ph: &ProgramHeader) -> Result<(), ElfError> {
+    let file_end = ph.offset.checked_add(ph.filesz).ok_or(ElfError::BadSegment)?;
+    if ph.filesz > ph.memsz || file_end > data.len() as u64 { return Err(ElfError::BadSegment); }
+
+    // 不能和核心共享的 RAM Superpage 重疊
+    let seg_end = ph.vaddr.checked_add(ph.memsz).ok_or(ElfError::BadSegment)? as usize;
+    if seg_end > USER_SPACE_END { return Err(ElfError::BadSegment); }
+    if (ph.vaddr as usize) < KERNEL_GIGAPAGE_END && seg_end > KERNEL_BASE { return Err(ElfError::BadSegment); }
+
+    // 以頁為單位映射，所以檔案位移與虛擬位址的頁內偏移必須相同
+    if ph.vaddr % 4096 != ph.offset % 4096 { return Err(ElfError::MisalignedSegment); }
+    if ph.align > 1 && (!ph.align.is_power_of_two() || ph.vaddr % ph.align != ph.offset % ph.align) {
+        return Err(ElfError::MisalignedSegment);
+    }
+
+    segment_flags(ph.flags)?;
+    Ok(())
+}
+
+/// Segment 的權限轉成 PTE 的權限 (W^X：同時可寫又可執行的 Segment 一律拒絕)
+fn segment_flags(flags: u32) -> Result<usize, ElfError> {
+    if flags & PF_W != 0 && flags & PF_X != 0 { return Err(ElfError::BadPermissions); }
+    let mut pte_flags = PTE_U;
+    if flags & PF_R != 0 { pte_flags |= PTE_R; }
+    // RISC-V 沒有「只可寫」的 PTE，可寫的頁面一定也可讀
+    if flags & PF_W != 0 { pte_flags |= PTE_R | PTE_W; }
+    if flags & PF_X != 0 { pte_flags |= PTE_X; }
+    // 沒有任何權限的 PTE 會被硬體當成指向下一層的 Page Table
+    if pte_flags == PTE_U { return Err(ElfError::BadPermissions); }
+    Ok(pte_flags)
+}
+
+/// Segment 占用的頁面範圍 [start, end) (check_segment 之後才能呼叫，不會溢位)
+fn page_range(ph: &ProgramHeader) -> (usize, usize) {
+    ((ph.vaddr as usize) & !0xFFF, (ph.vaddr + ph.memsz + 4095) as usize & !0xFFF)
+}
+
+/// Segment 在檔案中的資料裡，虛擬位址 vaddr 開始的 len 個位元組所在的檔案位移
+fn vaddr_to_offset(segments: &[&ProgramHeader], vaddr: u64, len: u64) -> Option<u64> {
+    let end = vaddr.checked_add(len)?;
+    segments.iter().find(|ph| ph.vaddr <= vaddr && end <= ph.vaddr + ph.filesz).map(|ph| ph.offset + (vaddr - ph.vaddr))
+}
+
+/// 寫入已經載入的 Segment (可能跨頁；經由實體位址寫入，所以唯讀的頁面也能修正)
+unsafe fn write_loaded(root: &PageTable, segments: &[&ProgramHeader], vaddr: u64, value: u64) -> Result<(), ElfError> {
+    let end = vaddr.checked_add(8).ok_or(ElfError::BadRelocation)?;
+    if !segments.iter().any(|ph| ph.vaddr <= vaddr && end <= ph.vaddr + ph.memsz) { return Err(ElfError::BadRelocation); }
+    for (i, byte) in value.to_le_bytes().iter().enumerate() {
+        let addr = vaddr as usize + i;
+        let page = unsafe { translate(root, addr) }.ok_or(ElfError::BadRelocation)?;
+        unsafe { *((page + (addr & 0xFFF)) as *mut u8) = *byte; }
+    }
+    Ok(())
+}
+
+/// 套用 PT_DYNAMIC 中 DT_RELA 的 Relocation (static-pie 只會有 R_RISCV_RELATIVE：*(bias + offset) = bias + addend)
+unsafe fn relocate(data: &[u8], root: &PageTable, phdrs: &[ProgramHeader], segments: &[&ProgramHeader], bias: u64) -> Result<(), ElfError> {
+    let Some(dynamic) = phdrs.iter().find(|ph| ph.type_ == PT_DYNAMIC) else { return Ok(()) };
+
+    let (mut rela, mut relasz, mut relaent) = (None, 0, size_of::<Rela>() as u64);
+    for i in 0..dynamic.filesz / size_of::<Dyn>() as u64 {
+        let offset = dynamic.offset.checked_add(i * size_of::<Dyn>() as u64).ok_or(ElfError::BadDynamic)?;
+        let entry: Dyn = read_struct(data, offset).map_err(|_| ElfError::BadDynamic)?;
+        match entry.tag {
+            DT_NULL => break,
+            DT_RELA => rela = Some(entry.val),
+            DT_RELASZ => relasz = entry.val,
+            DT_RELAENT => relaent = entry.val,
+            _ => {}
+        }
+    }
+    let Some(rela) = rela else { return Ok(()) };
+    if relaent != size_of::<Rela>() as u64 { return Err(ElfError::BadDynamic); }
+
+    // DT_RELA 是連結時的位址，要加上 bias 才能在 (已經加上 bias 的) Segment 中找到它在檔案中的位置
+    let table = vaddr_to_offset(segments, rela.wrapping_add(bias), relasz).ok_or(ElfError::BadDynamic)?;
+    for i in 0..relasz / relaent {
+        let entry: Rela = read_struct(data, table + i * relaent).map_err(|_| ElfError::BadDynamic)?;
+        match entry.info as u32 {
+            R_RISCV_NONE => {}
+            R_RISCV_RELATIVE => unsafe {
+                write_loaded(root, segments, entry.offset.wrapping_add(bias), bias.wrapping_add(entry.addend as u64))?;
+            },
+            _ => return Err(ElfError::BadRelocation),
+        }
     }
+    Ok(())
+}
+
+/// 解析 ELF 並載入到指定的 Page Table 中。
+/// ET_DYN (PIE) 會被搬到 base 開始的位置並套用 Relocation；ET_EXEC 則放在連結時決定的位址 (忽略 base)。
+/// 有 PT_INTERP 的執行檔需要解析符號，Relocation 全部交給動態載入器處理。
+/// 所有 Segment 的檢查都在配置任何頁面之前完成 (已映射的頁面在失敗時由呼叫者釋放)；
+/// reserved 是呼叫者自己要使用的範圍 (例如堆疊與它的 Guard Page)，Segment 不能蓋到它
+pub unsafe fn load_elf(data: &[u8], page_table: &mut PageTable, base: usize, reserved: Range<usize>) -> Result<LoadedElf, ElfError> {
+    let header = parse_header(data)?;
+    let mut phdrs = program_headers(data, &header)?;
+
+    // 載入位址的位移：PIE 的第一個 Segment 放在 base
+    let bias = if header.type_ == ET_DYN {
+        let lowest = phdrs.iter().filter(|ph| ph.type_ == PT_LOAD).map(|ph| ph.vaddr & !0xFFF).min().ok_or(ElfError::NoSegments)?;
+        (base as u64).wrapping_sub(lowest)
+    } else { 0 };
+    for ph in phdrs.iter_mut() {
+        ph.vaddr = ph.vaddr.checked_add(bias).ok_or(ElfError::BadSegment)?;
+    }
+
+    let segments: Vec<&ProgramHeader> = phdrs.iter().filter(|ph| ph.type_ == PT_LOAD && ph.memsz > 0).collect();
+    if segments.is_empty() { return Err(ElfError::NoSegments); }
+    for (i, ph) in segments.iter().enumerate() {
+        check_segment(data, ph)?;
+        // 每一頁只屬於一個 Segment：和其他 Segment 共用頁面時無法給出各自的權限
+        let (start, end) = page_range(ph);
+        if start < reserved.end && reserved.start < end { return Err(ElfError::OverlappingSegments); }
+        for other in &segments[..i] {
+            let (other_start, other_end) = page_range(other);
+            if start < other_end && other_start < end { return Err(ElfError::OverlappingSegments); }
+        }
+    }
+
+    let entry = header.entry.wrapping_add(bias);
+    let entry_ok = segments.iter().any(|ph| ph.flags & PF_X != 0 && (ph.vaddr..ph.vaddr + ph.memsz).contains(&entry));
+    if !entry_ok { return Err(ElfError::BadEntry); }
+
+    // 沒有 PT_PHDR 時，Program Header Table 通常被包含在第一個 LOAD Segment 中
+    let phdr = phdrs.iter().find(|ph| ph.type_ == PT_PHDR).map(|ph| ph.vaddr).or_else(|| {
+        segments.iter().find(|ph| (ph.offset..ph.offset + ph.filesz).contains(&header.phoff))
+            .map(|ph| ph.vaddr + (header.phoff - ph.offset))
+    });
 
-    let ph_table_ptr = unsafe { data.as_ptr().add(header.phoff as usize) };
-    
     // [修改] 使用傳入的 page_table 作為映射目標
     let root = page_table;
 
-    for i in 0..header.phnum {
-        let ph_ptr = unsafe { ph_table_ptr.add((i as usize) * (header.phentsize as usize)) };
-        let ph = unsafe { &*(ph_ptr as *const ProgramHeader) };
-
-        if ph.type_ == 1 { // LOAD Segment
-            let start_vpn = ph.vaddr >> 12;
-            let end_vpn = (ph.vaddr + ph.memsz + 4095) >> 12;
-
-            for vpn in start_vpn..end_vpn {
-                let page_vaddr = (vpn << 12) as usize;
-                
-                // 檢查是否已映射 (避免同一頁面重複分配導致資料覆蓋)
-                let mut paddr = unsafe { translate(root, page_vaddr).unwrap_or(0) };
-
-                if paddr == 0 {
-                    // 分配新的實體頁面
-                    paddr = alloc_frame();
-                    if paddr == 0 { return None; } 
-                    
-                    unsafe {
-                        map(root, page_vaddr, paddr, PTE_U | PTE_R | PTE_W | PTE_X);
-                    }
-                }
-
-                // 計算頁內偏移與寫入位置
-                // 寫入時使用 paddr (實體位址)，因為 M-Mode 核心不經過 MMU
-                let page_offset = if vpn == start_vpn { (ph.vaddr % 4096) as usize } else { 0 };
-                let dest_ptr = (paddr + page_offset) as *mut u8;
-                let page_remaining = 4096 - page_offset;
-                
-                let processed_len = (page_vaddr + page_offset) - (ph.vaddr as usize);
-                
-                if (processed_len as u64) < ph.filesz {
-                    let src_ptr = unsafe { data.as_ptr().add((ph.offset as usize) + processed_len) };
-                    let copy_len = core::cmp::min(page_remaining, (ph.filesz as usize) - processed_len);
-                    
-                    unsafe { core::ptr::copy_nonoverlapping(src_ptr, dest_ptr, copy_len); }
-                }
-                // BSS (補 0) 的部分由 alloc_frame 初始化時處理，這裡省略
+    for ph in &segments {
+        let flags = segment_flags(ph.flags)?;
+        let file_start = ph.vaddr as usize;
+        let file_end = file_start + ph.filesz as usize;
+        let (start, end) = page_range(ph);
+
+        for page_vaddr in (start..end).step_by(4096) {
+            // 同一個 Page Table 可能已經載入了另一個映像檔 (執行檔與動態載入器)
+            if unsafe { translate(root, page_vaddr) }.is_some() { return Err(ElfError::OverlappingSegments); }
+            let paddr = alloc_frame();
+            if paddr == 0 { return Err(ElfError::OutOfMemory); }
+
+            // 寫入時使用 paddr (實體位址)，核心的 Page Table 恆等映射整個 RAM。
+            // 整頁先清成 0，Segment 之前的部分與 BSS (filesz 之後到 memsz) 都不依賴 alloc_frame 的內容
+            unsafe { core::ptr::write_bytes(paddr as *mut u8, 0, 4096); }
+            let lo = core::cmp::max(page_vaddr, file_start);
+            let hi = core::cmp::min(page_vaddr + 4096, file_end);
+            if lo < hi {
+                // check_segment 已確認 [offset, offset + filesz) 在檔案範圍內
+                let src = &data[ph.offset as usize + (lo - file_start)..][..hi - lo];
+                unsafe { core::ptr::copy_nonoverlapping(src.as_ptr(), (paddr + (lo - page_vaddr)) as *mut u8, src.len()); }
+            }
+
+            if !unsafe { try_map(root, page_vaddr, paddr, flags) } {
+                frame::dealloc_frame(paddr);
+                return Err(ElfError::OutOfMemory);
             }
         }
     }
 
+    if !phdrs.iter().any(|ph| ph.type_ == PT_INTERP) {
+        unsafe { relocate(data, root, &phdrs, &segments, bias)?; }
+    }
+
     // 確保指令寫入可見
     unsafe { core::arch::asm!("fence.i"); }
-    
-    Some(header.entry)
-}
\ No newline at end of file
+
+    let end = segments.iter().map(|ph| page_range(ph).1).max().unwrap_or(0);
+    Ok(LoadedElf { entry, bias, end, phdr, phent: header.phentsize, phnum: header.phnum })
+}
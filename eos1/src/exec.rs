@@ -0,0 +1,238 @@
+// 建立新的使用者行程映像檔：從檔案系統讀出 ELF、載入到新的 Page Table，並依照 System V ABI 在堆疊上放好 argv / envp / auxv
+// SPAWN (建立新行程) 與 EXECVE (取代目前行程) 共用這裡的流程；
+// 動態連結的執行檔 (有 PT_INTERP) 會同時載入動態載入器，從載入器的 entry 開始執行
+use crate::mm::page_table::{self, new_user_page_table, free_user_page_table, PageTable, PTE_U, PTE_R, PTE_W};
+use crate::mm::frame;
+use crate::mm::vm::UserStack;
+use crate::fs;
+use crate::task::SchedulerGuard;
+use crate::elf::{self, ElfError};
+use crate::timer;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// ASLR：堆疊的頂端放在 USER_STACK_TOP 之下隨機的位置 (範圍 256MB)
+pub const USER_STACK_TOP: usize = 0xF000_1000;
+const STACK_ASLR_PAGES: u64 = 1 << 16;
+
+/// 使用者堆疊的預設大小與上限 (執行檔可以用 PT_GNU_STACK 指定，見 elf::stack_size)
+pub const DEFAULT_STACK_SIZE: usize = 64 * 1024;
+pub const MAX_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+// ASLR：PIE (ET_DYN) 載入到 PIE_BASE 之上隨機的位置 (範圍 64GB，仍在 Sv39 的使用者空間內)
+pub const PIE_BASE: usize = 0x10_0000_0000;
+const PIE_ASLR_PAGES: u64 = 1 << 24;
+
+// ASLR：brk 的 Heap 從程式的最後一個 Segment 之後隨機的位置開始 (範圍 32MB)
+const HEAP_ASLR_PAGES: u64 = 1 << 13;
+
+// ASLR：動態載入器放在 PIE 的範圍之上 (同樣是 64GB 的範圍)，兩者不會重疊
+pub const INTERP_BASE: usize = 0x20_0000_0000;
+
+/// auxv 最多的項目數 (包含結尾的 AT_NULL)
+const AUXV_ENTRIES: usize = 8;
+
+/// argv / envp 的數量上限 (防止使用者傳入沒有 NULL 結尾的陣列)
+pub const MAX_ARGS: usize = 64;
+
+// auxv 的類型 (與 Linux 的編號相同)
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7;
+pub const AT_ENTRY: usize = 9;
+pub const AT_RANDOM: usize = 25;
+
+/// SPAWN / EXECVE 失敗的原因，使用者拿到的回傳值是 code() (負數)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    /// path / argv / envp 的指標無效
+    BadAddress,
+    NotFound,
+    /// 參數與環境變數太多，放不進初始堆疊
+    TooBig,
+    OutOfMemory,
+    /// EXECVE 時還有其他 Thread 共用這個位址空間
+    Busy,
+    Elf(ElfError),
+}
+
+// 錯誤碼的編號與說明文字 (ELF_ERROR_BASE 等)，與 ulib 的 exec_error_message 共用
+include!("exec_errors.rs");
+
+// 每個 ElfError 都要有說明文字
+const _: () = assert!(ELF_ERROR_MESSAGES.len() == ElfError::ALL.len());
+
+impl ExecError {
+    pub fn code(self) -> isize {
+        match self {
+            ExecError::BadAddress => -1,
+            ExecError::NotFound => -2,
+            ExecError::TooBig => -3,
+            ExecError::OutOfMemory => -4,
+            ExecError::Busy => -5,
+            ExecError::Elf(e) => -(ELF_ERROR_BASE + ElfError::ALL.iter().position(|&x| x == e).unwrap_or(0) as isize),
+        }
+    }
+
+    pub fn from_code(code: isize) -> Option<ExecError> {
+        match code {
+            -1 => Some(ExecError::BadAddress),
+            -2 => Some(ExecError::NotFound),
+            -3 => Some(ExecError::TooBig),
+            -4 => Some(ExecError::OutOfMemory),
+            -5 => Some(ExecError::Busy),
+            _ => usize::try_from(-code - ELF_ERROR_BASE).ok()
+                .and_then(|i| ElfError::ALL.get(i)).map(|&e| ExecError::Elf(e)),
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        exec_error_description(self.code()).unwrap_or("unknown error")
+    }
+}
+
+impl From<ElfError> for ExecError {
+    fn from(e: ElfError) -> Self {
+        match e {
+            ElfError::OutOfMemory => ExecError::OutOfMemory,
+            e => ExecError::Elf(e),
+        }
+    }
+}
+
+/// 載入完成的映像檔：Page Table、開始執行時的 pc / sp、堆疊的範圍與 Heap 的起點 (brk 的初始值)
+/// (依照 System V ABI，程式從 sp 讀取 argc、argv、envp 與 auxv，暫存器 a0 為 0)
+pub struct Image {
+    pub root: *mut PageTable,
+    pub entry: u64,
+    pub sp: usize,
+    pub stack: UserStack,
+    pub heap_start: usize,
+}
+
+static RANDOM_STATE: AtomicU64 = AtomicU64::new(0);
+
+/// 給 AT_RANDOM 與 ASLR 的亂數 (splitmix64，以開機後的時間作為種子；不適合用在密碼學)
+fn random_u64() -> u64 {
+    let mut z = RANDOM_STATE.fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+        .wrapping_add(timer::now())
+        .wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// 讀取 path (相對於 cwd) 並建立映像檔。
+/// 讀取檔案時目前的 Task 會在 DISK_WAITERS 上睡眠 (見 fs)，返回後呼叫者要重新檢查它的狀態
+pub unsafe fn load_program(scheduler: &mut SchedulerGuard, cwd: u32, path: &str, argv: &[Vec<u8>], envp: &[Vec<u8>]) -> Result<Image, ExecError> {
+    let elf_data = fs::get_file_by_path(scheduler, cwd, path).ok_or(ExecError::NotFound)?;
+    // 動態載入器本身必須是靜態連結的 (由核心套用 Relocation)
+    let interp_data = match elf::interpreter(&elf_data)? {
+        Some(interp) => {
+            let data = fs::get_file_by_path(scheduler, cwd, &interp).ok_or(ExecError::NotFound)?;
+            if elf::interpreter(&data)?.is_some() { return Err(ExecError::Elf(ElfError::BadInterpreter)); }
+            Some(data)
+        }
+        None => None,
+    };
+
+    // 堆疊的大小：執行檔以 PT_GNU_STACK 指定 (不超過 MAX_STACK_SIZE)，否則使用 DEFAULT_STACK_SIZE
+    let stack_size = match elf::stack_size(&elf_data)? {
+        Some(size) => core::cmp::min(size, MAX_STACK_SIZE as u64).div_ceil(4096) as usize * 4096,
+        None => DEFAULT_STACK_SIZE,
+    };
+
+    // 初始堆疊 (由高到低)：參數與環境變數字串、AT_RANDOM 的 16 個位元組、
+    // auxv、envp (NULL 結尾)、argv (NULL 結尾)、argc ← sp (16 位元組對齊)
+    // 最多只能使用堆疊的 1/4，其餘留給程式本身
+    let strings: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * AUXV_ENTRIES;
+    let initial_size = strings + 16 + 16 + words * 8 + 16;
+    if initial_size > stack_size / 4 { return Err(ExecError::TooBig); }
+
+    let table = unsafe { new_user_page_table() };
+    if table.is_null() { return Err(ExecError::OutOfMemory); }
+
+    let stack = UserStack { top: USER_STACK_TOP - (random_u64() % STACK_ASLR_PAGES) as usize * 4096, size: stack_size };
+    let load_base = PIE_BASE + (random_u64() % PIE_ASLR_PAGES) as usize * 4096;
+    let interp_base = INTERP_BASE + (random_u64() % PIE_ASLR_PAGES) as usize * 4096;
+
+    // 程式的 Segment 不能蓋到堆疊與 Guard Page
+    let loaded = unsafe { elf::load_elf(&elf_data, &mut *table, load_base, stack.reserved()) }.and_then(|elf| match &interp_data {
+        Some(data) => unsafe { elf::load_elf(data, &mut *table, interp_base, stack.reserved()) }.map(|interp| (elf, Some(interp))),
+        None => Ok((elf, None)),
+    });
+    let (elf, interp) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            // 載入失敗：已經配置的頁面全部歸還
+            unsafe { free_user_page_table(table); }
+            return Err(e.into());
+        }
+    };
+
+    // 先在核心的暫存區排好堆疊頂端的內容 (buf 的結尾對應 stack.top)，再複製到實際的頁面。
+    // buf 是整頁的大小且初始全為 0，字串結尾的 0 不需要另外寫入
+    let mut buf = vec![0u8; initial_size.div_ceil(4096) * 4096];
+    let base = stack.top - buf.len();
+    let mut offset = buf.len();
+    let mut push_strings = |strings: &[Vec<u8>]| -> Vec<usize> {
+        strings.iter().map(|bytes| {
+            offset -= bytes.len() + 1;
+            buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+            base + offset
+        }).collect()
+    };
+    let argv_strs = push_strings(argv);
+    let envp_strs = push_strings(envp);
+
+    offset = (offset & !0xF) - 16;
+    buf[offset..offset + 8].copy_from_slice(&random_u64().to_le_bytes());
+    buf[offset + 8..offset + 16].copy_from_slice(&random_u64().to_le_bytes());
+    let random_vaddr = base + offset;
+
+    let mut auxv = Vec::with_capacity(AUXV_ENTRIES);
+    if let Some(phdr) = elf.phdr {
+        auxv.push((AT_PHDR, phdr as usize));
+        auxv.push((AT_PHENT, elf.phent as usize));
+        auxv.push((AT_PHNUM, elf.phnum as usize));
+    }
+    auxv.push((AT_PAGESZ, 4096));
+    if let Some(interp) = &interp { auxv.push((AT_BASE, interp.bias as usize)); }
+    auxv.push((AT_ENTRY, elf.entry as usize));
+    auxv.push((AT_RANDOM, random_vaddr));
+    auxv.push((AT_NULL, 0));
+
+    let mut words: Vec<usize> = Vec::with_capacity(words);
+    words.push(argv.len());
+    words.extend(&argv_strs);
+    words.push(0);
+    words.extend(&envp_strs);
+    words.push(0);
+    for (key, value) in auxv { words.push(key); words.push(value); }
+
+    offset = (offset - words.len() * 8) & !0xF;
+    for (i, word) in words.iter().enumerate() {
+        buf[offset + i * 8..offset + i * 8 + 8].copy_from_slice(&word.to_le_bytes());
+    }
+
+    // 只映射放得下初始內容的頁面，更低的部分在程式第一次存取時才由 Page Fault 配置 (見 vm::grow_stack)
+    for (i, chunk) in buf.chunks(4096).enumerate() {
+        let paddr = frame::alloc_frame();
+        if paddr == 0 || !unsafe { page_table::try_map(&mut *table, base + i * 4096, paddr, PTE_U | PTE_R | PTE_W) } {
+            if paddr != 0 { frame::dealloc_frame(paddr); }
+            unsafe { free_user_page_table(table); }
+            return Err(ExecError::OutOfMemory);
+        }
+        unsafe { core::ptr::copy_nonoverlapping(chunk.as_ptr(), paddr as *mut u8, chunk.len()); }
+    }
+
+    // 動態連結時先執行動態載入器，它從 AT_ENTRY 得知程式本身的 entry
+    let entry = interp.map_or(elf.entry, |interp| interp.entry);
+    let heap_start = elf.end + (random_u64() % HEAP_ASLR_PAGES) as usize * 4096;
+    Ok(Image { root: table, entry, sp: base + offset, stack, heap_start })
+}
@@ -0,0 +1,48 @@
+// SPAWN / EXECVE 錯誤碼的說明文字。這個檔案不是模組：核心的 exec.rs 與 user_app 的 ulib 都以 include! 引入，
+// 兩邊使用同一份表格，錯誤碼與訊息不會不一致
+//
+// -1..=-(EXEC_ERROR_MESSAGES.len()) 是一般的錯誤 (依 ExecError 的宣告順序)，
+// ELF 本身的錯誤從 -ELF_ERROR_BASE 開始，依 ElfError::ALL 的順序遞減
+
+/// ELF 錯誤的錯誤碼從 -ELF_ERROR_BASE 開始
+pub const ELF_ERROR_BASE: isize = 16;
+
+/// 錯誤碼 -1、-2、… 的說明
+pub const EXEC_ERROR_MESSAGES: [&str; 5] = [
+    "bad address",
+    "no such file",
+    "argument list too long",
+    "out of memory",
+    "other threads share the address space",
+];
+
+/// 錯誤碼 -ELF_ERROR_BASE、-(ELF_ERROR_BASE + 1)、… 的說明 (與 ElfError::ALL 的順序相同)
+pub const ELF_ERROR_MESSAGES: [&str; 18] = [
+    "truncated ELF file",
+    "not an ELF file",
+    "not a 64-bit ELF",
+    "not little-endian",
+    "unknown ELF version",
+    "not an executable",
+    "not a RISC-V binary",
+    "bad program header size",
+    "segment out of bounds",
+    "misaligned segment",
+    "overlapping segments",
+    "bad segment permissions (W^X)",
+    "no loadable segment",
+    "entry point outside executable segment",
+    "out of memory",
+    "bad dynamic section",
+    "unsupported relocation",
+    "bad program interpreter",
+];
+
+/// 錯誤碼的說明，不認識的錯誤碼回傳 None
+pub fn exec_error_description(code: isize) -> Option<&'static str> {
+    let index = usize::try_from(code.checked_neg()?).ok()?;
+    match index.checked_sub(ELF_ERROR_BASE as usize) {
+        Some(i) => ELF_ERROR_MESSAGES.get(i).copied(),
+        None => EXEC_ERROR_MESSAGES.get(index.checked_sub(1)?).copied(),
+    }
+}
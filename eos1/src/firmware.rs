@@ -0,0 +1,237 @@
+// 最小的 M-Mode Firmware：設定 PMP 與 trap 委派後以 S-Mode 進入核心，
+// 之後只負責 SBI 的 Timer / Console / HSM / RFENCE 呼叫，並把核心 Task 的 Syscall 轉回 S-Mode
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::sbi;
+use crate::task::MAX_HARTS;
+use crate::uart;
+
+const CLINT_MSIP: usize = 0x0200_0000;
+const CLINT_MTIMECMP: usize = 0x0200_4000;
+const FIRMWARE_STACK_SIZE: usize = 4096;
+
+const MIP_STIP: usize = 1 << 5;
+const MIE_MSIE: usize = 1 << 3;
+const MIE_MTIE: usize = 1 << 7;
+
+const MSTATUS_SIE: usize = 1 << 1;
+const MSTATUS_SPIE: usize = 1 << 5;
+const MSTATUS_SPP: usize = 1 << 8;
+const MSTATUS_MPP_S: usize = 1 << 11;
+const MSTATUS_FS_INITIAL: usize = 1 << 13;
+
+// 委派給 S-Mode 的例外：除了 S-Mode 的 ecall (SBI) 以外全部
+const DELEGATED_EXCEPTIONS: usize = (1 << 0) | (1 << 1) | (1 << 2) | (1 << 3) | (1 << 4) | (1 << 5)
+    | (1 << 6) | (1 << 7) | (1 << 8) | (1 << 12) | (1 << 13) | (1 << 15);
+// 委派給 S-Mode 的中斷：Software / Timer / External
+const DELEGATED_INTERRUPTS: usize = (1 << 1) | (1 << 5) | (1 << 9);
+
+// 開機的 hart (hart 0) 直接進入 rust_main；其他 hart 停在 Firmware 裡，
+// 直到核心以 SBI HSM hart_start 登記它們的 S-Mode 入口 (0 = 尚未啟動)
+static START_ADDR: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+static START_OPAQUE: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+const BOOT_HART: usize = 0;
+
+// 已經進入核心的 hart：只有它們會處理 RFENCE 的 IPI (還停在 wait_for_start 的 hart 沒有舊的 TLB 項目)
+static STARTED: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+// RFENCE：每個 hart 被要求清除 TLB 的次數與已經完成的次數，發出請求的一方等到 DONE 追上自己的請求才回傳
+static RFENCE_REQUESTED: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+static RFENCE_DONE: [AtomicUsize; MAX_HARTS] = [const { AtomicUsize::new(0) }; MAX_HARTS];
+
+unsafe extern "C" {
+    fn firmware_trap_vector();
+    fn rust_main(hartid: usize) -> !;
+    static _firmware_stack: u8;
+}
+
+/// 開機後的第一個 Rust 函式 (M-Mode，每個 hart 都會執行)，設定完成後以 S-Mode 跳進核心
+#[unsafe(no_mangle)]
+pub extern "C" fn firmware_main() -> ! {
+    unsafe {
+        let hartid: usize;
+        core::arch::asm!("csrr {}, mhartid", out(reg) hartid);
+
+        // 讓 S/U-Mode 可以存取所有實體記憶體 (沒有 PMP 項目時 S-Mode 什麼都不能存取)
+        core::arch::asm!("csrw pmpaddr0, {}", in(reg) !0usize);
+        core::arch::asm!("csrw pmpcfg0, {}", in(reg) 0x1Fusize);
+
+        let stack_top = (&raw const _firmware_stack as usize) + (hartid + 1) * FIRMWARE_STACK_SIZE;
+        core::arch::asm!("csrw mscratch, {}", in(reg) stack_top);
+        core::arch::asm!("csrw mtvec, {}", in(reg) firmware_trap_vector as *const () as usize);
+
+        core::arch::asm!("csrw medeleg, {}", in(reg) DELEGATED_EXCEPTIONS);
+        core::arch::asm!("csrw mideleg, {}", in(reg) DELEGATED_INTERRUPTS);
+        // S/U-Mode 可以讀取 cycle / time / instret
+        core::arch::asm!("csrw mcounteren, {}", in(reg) 0b111usize);
+        core::arch::asm!("csrw satp, zero");
+
+        let (entry, opaque) = if hartid == BOOT_HART {
+            (rust_main as *const () as usize, 0)
+        } else {
+            wait_for_start(hartid)
+        };
+
+        core::arch::asm!("csrw mstatus, {}", in(reg) MSTATUS_MPP_S | MSTATUS_FS_INITIAL);
+        // 在 S/U-Mode 執行時 Machine Software 中斷 (RFENCE 的 IPI) 一定會被處理
+        STARTED[hartid].store(true, Ordering::Release);
+        core::arch::asm!("csrs mie, {}", in(reg) MIE_MSIE);
+        // 沿用開機堆疊，進入核心後不會再回到這裡
+        core::arch::asm!(
+            "csrw mepc, {entry}",
+            "mret",
+            entry = in(reg) entry,
+            in("a0") hartid,
+            in("a1") opaque,
+            options(noreturn)
+        );
+    }
+}
+
+/// 次要 hart 以 wfi 等待 hart_start 送來的 Machine Software 中斷 (IPI)，回傳 (入口, opaque)。
+/// 此時 mstatus.MIE = 0，中斷只會喚醒 wfi 而不會進入 trap
+unsafe fn wait_for_start(hartid: usize) -> (usize, usize) {
+    unsafe {
+        core::arch::asm!("csrs mie, {}", in(reg) MIE_MSIE);
+        let entry = loop {
+            let entry = START_ADDR[hartid].load(Ordering::Acquire);
+            if entry != 0 { break entry; }
+            core::arch::asm!("wfi");
+        };
+        ((CLINT_MSIP + 4 * hartid) as *mut u32).write_volatile(0);
+        core::arch::asm!("csrc mie, {}", in(reg) MIE_MSIE);
+        (entry, START_OPAQUE[hartid].load(Ordering::Relaxed))
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn firmware_trap(regs: &mut [usize; 32]) {
+    let mcause: usize;
+    unsafe { core::arch::asm!("csrr {}, mcause", out(reg) mcause); }
+    let is_interrupt = (mcause >> 63) != 0;
+    let code = mcause & 0xfff;
+
+    match (is_interrupt, code) {
+        // Machine Timer：轉成 Supervisor Timer 中斷交給核心，直到核心設定下一次的時間前先關掉
+        (true, 7) => unsafe {
+            core::arch::asm!("csrs mip, {}", in(reg) MIP_STIP);
+            core::arch::asm!("csrc mie, {}", in(reg) MIE_MTIE);
+        },
+        // Machine Software：其他 hart 的 RFENCE 請求
+        (true, 3) => service_rfence(current_hart()),
+        // S-Mode 的 ecall：SBI 呼叫，或是在 S-Mode 執行的核心 Task 發出的 Syscall
+        (false, 9) => {
+            let eid = regs[17];
+            if eid == sbi::EID_TIME || eid == sbi::EID_DBCN || eid == sbi::EID_HSM || eid == sbi::EID_RFENCE {
+                let (error, value) = handle_sbi(eid, regs[16], regs[10], regs[11], regs[12]);
+                regs[10] = error as usize;
+                regs[11] = value;
+                unsafe {
+                    let mepc: usize;
+                    core::arch::asm!("csrr {}, mepc", out(reg) mepc);
+                    core::arch::asm!("csrw mepc, {}", in(reg) mepc + 4);
+                }
+            } else {
+                unsafe { redirect_to_supervisor(code); }
+            }
+        },
+        _ => {
+            let mepc: usize;
+            let mtval: usize;
+            unsafe {
+                core::arch::asm!("csrr {}, mepc", out(reg) mepc);
+                core::arch::asm!("csrr {}, mtval", out(reg) mtval);
+            }
+            println!("[Firmware] Unexpected trap: mcause={:#x} mepc={:#x} mtval={:#x}", mcause, mepc, mtval);
+            loop { unsafe { core::arch::asm!("wfi"); } }
+        }
+    }
+}
+
+fn handle_sbi(eid: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> (isize, usize) {
+    match (eid, fid) {
+        (sbi::EID_TIME, sbi::TIME_SET_TIMER) => unsafe {
+            ((CLINT_MTIMECMP + 8 * current_hart()) as *mut u64).write_volatile(arg0 as u64);
+            core::arch::asm!("csrc mip, {}", in(reg) MIP_STIP);
+            core::arch::asm!("csrs mie, {}", in(reg) MIE_MTIE);
+            (sbi::SUCCESS, 0)
+        },
+        (sbi::EID_DBCN, sbi::DBCN_WRITE_BYTE) => {
+            uart::_putchar(arg0 as u8);
+            (sbi::SUCCESS, 0)
+        },
+        // hart_start(hartid, start_addr, opaque)：登記入口後以 IPI 叫醒停住的 hart
+        (sbi::EID_HSM, sbi::HSM_HART_START) => {
+            if arg0 >= MAX_HARTS || arg0 == BOOT_HART { return (sbi::ERR_INVALID_PARAM, 0); }
+            START_OPAQUE[arg0].store(arg2, Ordering::Relaxed);
+            if START_ADDR[arg0].compare_exchange(0, arg1, Ordering::Release, Ordering::Relaxed).is_err() {
+                return (sbi::ERR_ALREADY_AVAILABLE, 0);
+            }
+            unsafe { ((CLINT_MSIP + 4 * arg0) as *mut u32).write_volatile(1); }
+            (sbi::SUCCESS, 0)
+        },
+        // remote_sfence_vma(hart_mask, hart_mask_base, ...)：以 IPI 要求其他 hart 清除整個 TLB，
+        // 等到它們都完成才回傳 (核心在釋放頁面之前需要確定沒有 hart 還能經由舊的 TLB 項目存取它)
+        (sbi::EID_RFENCE, sbi::RFENCE_SFENCE_VMA) => {
+            let hartid = current_hart();
+            let mut waits = [0usize; MAX_HARTS];
+            for (hart, wait) in waits.iter_mut().enumerate() {
+                let selected = arg1 == usize::MAX
+                    || hart.checked_sub(arg1).is_some_and(|bit| bit < usize::BITS as usize && arg0 & (1 << bit) != 0);
+                if !selected { continue; }
+                if hart == hartid {
+                    unsafe { core::arch::asm!("sfence.vma"); }
+                } else if STARTED[hart].load(Ordering::Acquire) {
+                    *wait = RFENCE_REQUESTED[hart].fetch_add(1, Ordering::AcqRel) + 1;
+                    unsafe { ((CLINT_MSIP + 4 * hart) as *mut u32).write_volatile(1); }
+                }
+            }
+            for (hart, &wait) in waits.iter().enumerate() {
+                while wait != 0 && RFENCE_DONE[hart].load(Ordering::Acquire) < wait {
+                    // 對方可能同時也在等我們 (在 M-Mode 裡中斷是關閉的)，等待時先處理自己收到的請求
+                    service_rfence(hartid);
+                    core::hint::spin_loop();
+                }
+            }
+            (sbi::SUCCESS, 0)
+        },
+        _ => (sbi::ERR_NOT_SUPPORTED, 0),
+    }
+}
+
+fn current_hart() -> usize {
+    let hartid: usize;
+    unsafe { core::arch::asm!("csrr {}, mhartid", out(reg) hartid); }
+    hartid
+}
+
+/// 處理送給這個 hart 的 RFENCE 請求。先清除 IPI 再讀取請求的次數，之後才送來的請求會再觸發一次中斷
+fn service_rfence(hartid: usize) {
+    unsafe { ((CLINT_MSIP + 4 * hartid) as *mut u32).write_volatile(0); }
+    let requested = RFENCE_REQUESTED[hartid].load(Ordering::Acquire);
+    if RFENCE_DONE[hartid].load(Ordering::Relaxed) >= requested { return; }
+    unsafe { core::arch::asm!("sfence.vma"); }
+    RFENCE_DONE[hartid].store(requested, Ordering::Release);
+}
+
+/// 假裝這個 trap 是直接委派給 S-Mode 的：設定 sepc / scause / sstatus 後從 stvec 繼續執行
+unsafe fn redirect_to_supervisor(cause: usize) {
+    unsafe {
+        let mepc: usize;
+        let mut mstatus: usize;
+        let stvec: usize;
+        core::arch::asm!("csrr {}, mepc", out(reg) mepc);
+        core::arch::asm!("csrr {}, mstatus", out(reg) mstatus);
+        core::arch::asm!("csrr {}, stvec", out(reg) stvec);
+
+        // SPP = S-Mode、SPIE = 原本的 SIE、SIE = 0 (和硬體進入 S-Mode trap 時相同)
+        mstatus |= MSTATUS_SPP;
+        if mstatus & MSTATUS_SIE != 0 { mstatus |= MSTATUS_SPIE; } else { mstatus &= !MSTATUS_SPIE; }
+        mstatus &= !MSTATUS_SIE;
+
+        core::arch::asm!("csrw sepc, {}", in(reg) mepc);
+        core::arch::asm!("csrw scause, {}", in(reg) cause);
+        core::arch::asm!("csrw stval, zero");
+        core::arch::asm!("csrw mstatus, {}", in(reg) mstatus);
+        core::arch::asm!("csrw mepc, {}", in(reg) stvec);
+    }
+}
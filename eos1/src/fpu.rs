@@ -0,0 +1,105 @@
+// 浮點暫存器 (f0-f31、fcsr) 的保存與恢復，狀態放在每個 Task 的 Context 中
+// sstatus.FS 由硬體維護：Off / Initial / Clean / Dirty，Task 寫入 FP 暫存器後會變成 Dirty，
+// 所以只有 Dirty 時才需要保存 (沒用到浮點數的 Task 不必付出任何代價)
+use crate::task::Context;
+
+pub const SSTATUS_FS: u64 = 3 << 13;
+const FS_INITIAL: u64 = 1 << 13;
+const FS_CLEAN: u64 = 2 << 13;
+const FS_DIRTY: u64 = 3 << 13;
+
+/// 進入核心時呼叫：Task 改過 FP 暫存器才存進 Context，之後標成 Clean
+/// (此時 sstatus.FS 仍是 Task 的 Dirty，可以直接存取 FP 暫存器)
+pub fn save(ctx: &mut Context) {
+    if ctx.sstatus & SSTATUS_FS != FS_DIRTY { return; }
+    let fcsr: u64;
+    unsafe {
+        core::arch::asm!(
+            "fsd f0, 0({fregs})",
+            "fsd f1, 8({fregs})",
+            "fsd f2, 16({fregs})",
+            "fsd f3, 24({fregs})",
+            "fsd f4, 32({fregs})",
+            "fsd f5, 40({fregs})",
+            "fsd f6, 48({fregs})",
+            "fsd f7, 56({fregs})",
+            "fsd f8, 64({fregs})",
+            "fsd f9, 72({fregs})",
+            "fsd f10, 80({fregs})",
+            "fsd f11, 88({fregs})",
+            "fsd f12, 96({fregs})",
+            "fsd f13, 104({fregs})",
+            "fsd f14, 112({fregs})",
+            "fsd f15, 120({fregs})",
+            "fsd f16, 128({fregs})",
+            "fsd f17, 136({fregs})",
+            "fsd f18, 144({fregs})",
+            "fsd f19, 152({fregs})",
+            "fsd f20, 160({fregs})",
+            "fsd f21, 168({fregs})",
+            "fsd f22, 176({fregs})",
+            "fsd f23, 184({fregs})",
+            "fsd f24, 192({fregs})",
+            "fsd f25, 200({fregs})",
+            "fsd f26, 208({fregs})",
+            "fsd f27, 216({fregs})",
+            "fsd f28, 224({fregs})",
+            "fsd f29, 232({fregs})",
+            "fsd f30, 240({fregs})",
+            "fsd f31, 248({fregs})",
+            "frcsr {fcsr}",
+            fregs = in(reg) ctx.fregs.as_mut_ptr(),
+            fcsr = out(reg) fcsr,
+        );
+    }
+    ctx.fcsr = fcsr;
+    ctx.sstatus = (ctx.sstatus & !SSTATUS_FS) | FS_CLEAN;
+}
+
+/// Task 換到這個 hart 上執行前呼叫：從 Context 載入它的 FP 暫存器 (FS = Off 的 Task 不使用浮點數)
+pub fn restore(ctx: &mut Context) {
+    if ctx.sstatus & SSTATUS_FS == 0 { return; }
+    unsafe {
+        // 核心目前的 sstatus.FS 可能是 Off，先打開才能存取 FP 暫存器 (回到 Task 時會換成 Context 的 sstatus)
+        core::arch::asm!("csrs sstatus, {}", in(reg) FS_INITIAL);
+        core::arch::asm!(
+            "fld f0, 0({fregs})",
+            "fld f1, 8({fregs})",
+            "fld f2, 16({fregs})",
+            "fld f3, 24({fregs})",
+            "fld f4, 32({fregs})",
+            "fld f5, 40({fregs})",
+            "fld f6, 48({fregs})",
+            "fld f7, 56({fregs})",
+            "fld f8, 64({fregs})",
+            "fld f9, 72({fregs})",
+            "fld f10, 80({fregs})",
+            "fld f11, 88({fregs})",
+            "fld f12, 96({fregs})",
+            "fld f13, 104({fregs})",
+            "fld f14, 112({fregs})",
+            "fld f15, 120({fregs})",
+            "fld f16, 128({fregs})",
+            "fld f17, 136({fregs})",
+            "fld f18, 144({fregs})",
+            "fld f19, 152({fregs})",
+            "fld f20, 160({fregs})",
+            "fld f21, 168({fregs})",
+            "fld f22, 176({fregs})",
+            "fld f23, 184({fregs})",
+            "fld f24, 192({fregs})",
+            "fld f25, 200({fregs})",
+            "fld f26, 208({fregs})",
+            "fld f27, 216({fregs})",
+            "fld f28, 224({fregs})",
+            "fld f29, 232({fregs})",
+            "fld f30, 240({fregs})",
+            "fld f31, 248({fregs})",
+            "fscsr {fcsr}",
+            fregs = in(reg) ctx.fregs.as_ptr(),
+            fcsr = in(reg) ctx.fcsr,
+        );
+    }
+    // 暫存器內容與 Context 相同：在 Task 再次寫入前都不需要保存
+    ctx.sstatus = (ctx.sstatus & !SSTATUS_FS) | FS_CLEAN;
+}
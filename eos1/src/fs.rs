@@ -1,12 +1,22 @@
 use alloc::vec::Vec;
 use alloc::string::String;
+use crate::task::SchedulerGuard;
 use crate::virtio;
 
+// 讀取檔案與目錄時以 virtio::read_sector 等待磁碟 (目前的 Task 睡眠，其他 hart 繼續執行)，
+// 所以呼叫者要傳入持有的排程器，返回後重新取得 current_task。
+// write_file 則在持有鎖時一次做完 (輪詢磁碟)：讀取端睡眠時看到的資料仍然一致，
+// 因為寫入一律放在新的 Sector，最後才更新目錄項目
+
 // 0=File, 1=Directory
 pub const TYPE_FILE: u8 = 0;
 pub const TYPE_DIR: u8 = 1;
 
-static mut CURRENT_DIR_SECTOR: u32 = 1;
+// 根目錄所在的 Sector，也是每個 Task 的預設工作目錄
+pub const ROOT_DIR_SECTOR: u32 = 1;
+
+// FILE_WRITE 一次最多寫入的大小 (整個檔案都要先複製到核心 Heap；磁碟只有 32MB)
+pub const MAX_FILE_SIZE: usize = 4 * 1024 * 1024;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -26,19 +36,38 @@ struct DirEntry {
     _padding: [u8; 23],
 }
 
-// Helper: 讀取指定 Sector 的 Directory Table
-fn read_dir_entries(sector: u32) -> Vec<DirEntry> {
-    let dir_data = virtio::read_disk(sector as u64);
+// Directory Table 可以跨越連續的多個 Sector，以第一個空的項目 (start_sector == 0) 結尾。
+// mkfs 為每個目錄保留 MAX_DIR_SECTORS 個 Sector (根目錄是 Sector 1..=9，資料從 Sector 10 開始)，
+// 還沒用到的部分全為 0，write_file 可以把目錄表延伸進去
+const DIR_ENTRIES_PER_SECTOR: usize = 8;
+const MAX_DIR_SECTORS: u32 = 9;
+
+// Helper: 以 read 讀取從指定 Sector 開始的 Directory Table，回傳每個 Sector 的 (編號, 內容)
+fn read_dir_sectors(sector: u32, mut read: impl FnMut(u64) -> [u8; 512]) -> Vec<(u32, [u8; 512])> {
+    let mut sectors = Vec::new();
+    for current in sector..sector + MAX_DIR_SECTORS {
+        let data = read(current as u64);
+        let entries = unsafe { core::slice::from_raw_parts(data.as_ptr() as *const DirEntry, DIR_ENTRIES_PER_SECTOR) };
+        let has_end = entries.iter().any(|e| e.start_sector == 0);
+        sectors.push((current, data));
+        if has_end { break; }
+    }
+    sectors
+}
+
+// Helper: 讀取指定 Sector 開始的 Directory Table
+fn read_dir_entries(scheduler: &mut SchedulerGuard, sector: u32) -> Vec<DirEntry> {
     let mut entries = Vec::new();
-    let raw_slice = unsafe { core::slice::from_raw_parts(dir_data.as_ptr() as *const DirEntry, 8) };
-    entries.extend_from_slice(raw_slice);
+    for (_, dir_data) in read_dir_sectors(sector, |s| virtio::read_sector(scheduler, s)) {
+        let raw_slice = unsafe { core::slice::from_raw_parts(dir_data.as_ptr() as *const DirEntry, DIR_ENTRIES_PER_SECTOR) };
+        entries.extend_from_slice(raw_slice);
+    }
     entries
 }
 
-pub fn list_files() -> Vec<(u8, String)> {
+pub fn list_files(scheduler: &mut SchedulerGuard, dir_sector: u32) -> Vec<(u8, String)> {
     let mut list = Vec::new();
-    let dir_sector = unsafe { CURRENT_DIR_SECTOR };
-    let entries = read_dir_entries(dir_sector);
+    let entries = read_dir_entries(scheduler, dir_sector);
 
     for entry in entries {
         if entry.start_sector == 0 { continue; }
@@ -52,14 +81,14 @@ pub fn list_files() -> Vec<(u8, String)> {
     list
 }
 
-pub fn change_dir(name: &str) -> isize {
+// cwd 為呼叫者 (Task) 的工作目錄，成功時直接更新
+pub fn change_dir(scheduler: &mut SchedulerGuard, cwd: &mut u32, name: &str) -> isize {
     if name == "/" {
-        unsafe { CURRENT_DIR_SECTOR = 1; }
+        *cwd = ROOT_DIR_SECTOR;
         return 0;
     }
 
-    let dir_sector = unsafe { CURRENT_DIR_SECTOR };
-    let entries = read_dir_entries(dir_sector);
+    let entries = read_dir_entries(scheduler, *cwd);
 
     for entry in entries {
         let name_end = entry.name.iter().position(|&c| c == 0).unwrap_or(32);
@@ -67,7 +96,7 @@ pub fn change_dir(name: &str) -> isize {
 
         if entry_name == name {
             if entry.file_type == TYPE_DIR {
-                unsafe { CURRENT_DIR_SECTOR = entry.start_sector; }
+                *cwd = entry.start_sector;
                 return 0;
             } else {
                 return -2;
@@ -77,9 +106,8 @@ pub fn change_dir(name: &str) -> isize {
     -1
 }
 
-pub fn get_file_content(name: &str) -> Option<Vec<u8>> {
-    let dir_sector = unsafe { CURRENT_DIR_SECTOR };
-    let entries = read_dir_entries(dir_sector);
+pub fn get_file_content(scheduler: &mut SchedulerGuard, dir_sector: u32, name: &str) -> Option<Vec<u8>> {
+    let entries = read_dir_entries(scheduler, dir_sector);
 
     for entry in entries {
         let name_end = entry.name.iter().position(|&c| c == 0).unwrap_or(32);
@@ -93,7 +121,7 @@ pub fn get_file_content(name: &str) -> Option<Vec<u8>> {
             let mut remaining = entry.size;
 
             while remaining > 0 {
-                let sector_data = virtio::read_disk(current_sec as u64);
+                let sector_data = virtio::read_sector(scheduler, current_sec as u64);
                 let copy_len = core::cmp::min(remaining as usize, 512);
                 content.extend_from_slice(&sector_data[0..copy_len]);
                 remaining -= copy_len as u32;
@@ -106,35 +134,53 @@ pub fn get_file_content(name: &str) -> Option<Vec<u8>> {
 }
 
 // [修正] 恢復並修正寫入功能
-pub fn write_file(name: &str, data: &[u8]) -> isize {
+/// 依路徑讀取檔案：以 / 開頭時從根目錄開始，否則從 cwd 開始，中間的每一段都必須是目錄
+pub fn get_file_by_path(scheduler: &mut SchedulerGuard, cwd: u32, path: &str) -> Option<Vec<u8>> {
+    let (mut dir, rest) = match path.strip_prefix('/') {
+        Some(rest) => (ROOT_DIR_SECTOR, rest),
+        None => (cwd, path),
+    };
+    let mut parts = rest.split('/').filter(|p| !p.is_empty());
+    let name = parts.next_back()?;
+    for part in parts {
+        if change_dir(scheduler, &mut dir, part) != 0 { return None; }
+    }
+    get_file_content(scheduler, dir, name)
+}
+
+pub fn write_file(dir_sector: u32, name: &str, data: &[u8]) -> isize {
     // 1. 讀取 Superblock (為了檢查是否滿了，雖然這裡簡化處理)
     let sb_data = virtio::read_disk(0);
     let sb = unsafe { &*(sb_data.as_ptr() as *const Superblock) };
     if sb.magic != 0x53465331 { return -1; }
+    if data.len() > MAX_FILE_SIZE { return -1; }
 
     // 2. 讀取當前目錄
-    let dir_sector = unsafe { CURRENT_DIR_SECTOR };
     // 注意：我們要修改它，所以不能只用 read_dir_entries (它回傳 Vec clone)
-    let mut dir_buf = virtio::read_disk(dir_sector as u64);
-    let entries = unsafe { core::slice::from_raw_parts_mut(dir_buf.as_mut_ptr() as *mut DirEntry, 8) };
+    let mut dir_sectors = read_dir_sectors(dir_sector, virtio::read_disk);
+    let entry_at = |sectors: &mut Vec<(u32, [u8; 512])>, i: usize| -> *mut DirEntry {
+        unsafe { (sectors[i / DIR_ENTRIES_PER_SECTOR].1.as_mut_ptr() as *mut DirEntry).add(i % DIR_ENTRIES_PER_SECTOR) }
+    };
 
     // 3. 找空位或同名檔案
     let mut target_idx = None;
     let mut free_idx = None;
+    let mut free_count = 0;
     
     // 尋找最大使用的 Sector (全域搜尋有點難，這裡我們只搜尋當前目錄的最大值作為起點，這是個 Bug 但堪用)
     // 正確做法是 Superblock 應該記錄 next_free_sector
     let mut max_sector = 50; // 隨便抓個安全值，假設前面的都被用掉了
 
-    for i in 0..8 {
-        let entry = &entries[i];
+    for i in 0..dir_sectors.len() * DIR_ENTRIES_PER_SECTOR {
+        let entry = unsafe { &*entry_at(&mut dir_sectors, i) };
         if entry.start_sector == 0 {
             if free_idx.is_none() { free_idx = Some(i); }
+            free_count += 1;
             continue; 
         }
 
         // 更新 max sector
-        let used_sectors = (entry.size + 511) / 512;
+        let used_sectors = entry.size.div_ceil(512);
         let end = entry.start_sector + used_sectors;
         if end > max_sector { max_sector = end; }
 
@@ -146,8 +192,11 @@ pub fn write_file(name: &str, data: &[u8]) -> isize {
         }
     }
 
+    // 只剩結尾的空項目時，把目錄表延伸到下一個保留的 Sector：原本的結尾拿來放新的項目，新的 Sector 成為結尾
+    let grow = target_idx.is_none() && free_count == 1 && (dir_sectors.len() as u32) < MAX_DIR_SECTORS;
     let idx = if let Some(i) = target_idx { i } 
-              else if let Some(i) = free_idx { i } 
+              // 最後一個空位是目錄表的結尾，除非目錄表還能延伸，否則不能拿來用
+              else if let Some(i) = free_idx && (free_count > 1 || grow) { i } 
               else { return -2; }; // 目錄滿了
 
     // 4. 寫入資料
@@ -167,8 +216,11 @@ pub fn write_file(name: &str, data: &[u8]) -> isize {
         current_sec += 1;
     }
 
-    // 5. 更新目錄 Entry
-    let entry = &mut entries[idx];
+    // 5. 更新目錄 Entry (延伸時先寫好新的結尾，才不會有讀到一半的目錄表)
+    if grow {
+        virtio::write_disk((dir_sector + dir_sectors.len() as u32) as u64, &[0u8; 512]);
+    }
+    let entry = unsafe { &mut *entry_at(&mut dir_sectors, idx) };
     let name_bytes = name.as_bytes();
     let copy_len = core::cmp::min(name_bytes.len(), 32);
     
@@ -179,8 +231,9 @@ pub fn write_file(name: &str, data: &[u8]) -> isize {
     entry.size = data.len() as u32;
     entry.file_type = TYPE_FILE;
 
-    // 6. 寫回目錄表
-    virtio::write_disk(dir_sector as u64, &dir_buf);
+    // 6. 寫回目錄表 (只有被修改的那個 Sector)
+    let (sector, dir_buf) = &dir_sectors[idx / DIR_ENTRIES_PER_SECTOR];
+    virtio::write_disk(*sector as u64, dir_buf);
 
     0
 }
\ No newline at end of file
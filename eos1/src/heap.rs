@@ -1,6 +1,7 @@
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::null_mut;
 use core::mem::{size_of, align_of};
+use crate::sync::SpinLock;
 
 // 1MB 堆積空間
 const HEAP_SIZE: usize = 1024 * 1024;
@@ -26,7 +27,7 @@ pub struct LinkedListAllocator {
     head: *mut ListNode,
 }
 
-unsafe impl Sync for LinkedListAllocator {}
+unsafe impl Send for LinkedListAllocator {}
 
 impl LinkedListAllocator {
     pub const fn new() -> Self {
@@ -56,9 +57,12 @@ fn align_up(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
 }
 
-unsafe impl GlobalAlloc for LinkedListAllocator {
+// 所有 hart 共用同一個 Heap：配置與釋放都要先拿到鎖
+pub struct LockedHeap(SpinLock<LinkedListAllocator>);
+
+unsafe impl GlobalAlloc for LockedHeap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let allocator = unsafe { &mut *(&raw mut ALLOCATOR) };
+        let mut allocator = self.0.lock();
         
         let mut prev: *mut ListNode = null_mut();
         let mut curr = allocator.head;
@@ -124,7 +128,7 @@ unsafe impl GlobalAlloc for LinkedListAllocator {
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let allocator = unsafe { &mut *(&raw mut ALLOCATOR) };
+        let mut allocator = self.0.lock();
 
         // 因為 alloc 時已經強制 min_size，所以這裡寫入是安全的
         let min_size = size_of::<ListNode>();
@@ -145,11 +149,8 @@ unsafe impl GlobalAlloc for LinkedListAllocator {
 }
 
 #[global_allocator]
-static mut ALLOCATOR: LinkedListAllocator = LinkedListAllocator::new();
+static ALLOCATOR: LockedHeap = LockedHeap(SpinLock::new(LinkedListAllocator::new()));
 
 pub fn init() {
-    unsafe {
-        let ptr = &raw mut ALLOCATOR;
-        (*ptr).init();
-    }
+    unsafe { ALLOCATOR.0.lock().init(); }
 }
\ No newline at end of file
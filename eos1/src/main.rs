@@ -17,27 +17,33 @@ mod trap;
 mod syscall;
 mod virtio;
 mod shell; 
+mod sbi;
+mod firmware;
+mod sync;
+mod fpu;
+mod exec;
 
 use core::panic::PanicInfo;
-use task::{Task, Scheduler};
-#[allow(unused_imports)]
-use crate::mm::page_table::{PageTable, PTE_R, PTE_W, PTE_X, PTE_U, KERNEL_PAGE_TABLE};
+use task::{Task, MAX_HARTS};
+use crate::mm::page_table::{PageTable, PTE_R, PTE_W, PTE_X, PTE_G, PTE_A, PTE_D, KERNEL_BASE};
 
 core::arch::global_asm!(include_str!("entry.S"));
 core::arch::global_asm!(include_str!("trap.S"));
+core::arch::global_asm!(include_str!("firmware.S"));
+core::arch::global_asm!(include_str!("switch.S"));
 
 unsafe extern "C" { fn trap_vector(); }
 
+/// 核心入口 (S-Mode)，由 firmware_main 以 mret 跳進來
 #[unsafe(no_mangle)]
-pub extern "C" fn rust_main() -> ! {
+pub extern "C" fn rust_main(hartid: usize) -> ! {
+    task::set_hart_id(hartid);
+
     println!("-----------------------------------");
     println!("   EOS Refactored (v1.0)           ");
     println!("-----------------------------------");
 
     unsafe {
-        core::arch::asm!("csrw pmpaddr0, {}", in(reg) !0usize);
-        core::arch::asm!("csrw pmpcfg0, {}", in(reg) 0x1Fusize);
-
         mm::frame::init();
         heap::init();
         
@@ -52,55 +58,78 @@ pub extern "C" fn rust_main() -> ! {
         
         println!("[Kernel] Mapping MMIO (PLIC & VirtIO)...");
         let mut addr = 0x0C00_0000;
-        let end_plic = 0x0C20_1000; 
+        // 包含每個 hart 的 S-Mode context (threshold / claim 在 0x0C20_0000 + 0x1000 * context)
+        let end_plic = 0x0C21_0000; 
         while addr < end_plic { mm::page_table::map(root, addr, addr, PTE_R | PTE_W); addr += 4096; } 
         
         let mut addr = 0x1000_0000;
         let end_mmio = 0x1000_8000;
         while addr < end_mmio { mm::page_table::map(root, addr, addr, PTE_R | PTE_W); addr += 4096; }
 
-        let start = 0x8000_0000; let end = 0x8800_0000; 
-        let mut addr = start;
-        while addr < end { mm::page_table::map(root, addr, addr, PTE_R | PTE_W | PTE_X | PTE_U); addr += 4096; }
+        // RAM 以一個 Superpage 恆等映射，沒有 PTE_U：只有 S-Mode (核心與核心 Task) 可以存取。
+        // PTE_G 代表所有位址空間共享，new_user_page_table 會把它複製到每個使用者 Page Table
+        root.entries[(KERNEL_BASE >> 30) & 0x1FF].set_entry(KERNEL_BASE >> 12, PTE_R | PTE_W | PTE_X | PTE_G | PTE_A | PTE_D);
 
         let satp_val = (8 << 60) | ((root_ptr as usize) >> 12);
+        mm::page_table::KERNEL_SATP = satp_val;
         core::arch::asm!("csrw satp, {}", "sfence.vma", in(reg) satp_val);
         println!("[Kernel] MMU Enabled.");
 
-        Scheduler::init();
-        let scheduler = task::get_scheduler();
-        scheduler.spawn(Task::new_kernel(0, shell::shell_entry));
-        scheduler.spawn(Task::new_kernel(1, shell::bg_task));
+        {
+            let mut scheduler = task::SCHEDULER.lock();
+            // PID 依建立順序配發：Shell = 0、init = 1 (task::INIT_PID)、開機 hart 的 Idle = 2
+            let shell_pid = scheduler.alloc_pid();
+            scheduler.spawn(Task::new_kernel(shell_pid, shell::shell_entry));
+            let init_pid = scheduler.alloc_pid();
+            scheduler.spawn(Task::new_kernel(init_pid, shell::init_entry));
+            let idle_pid = scheduler.alloc_pid();
+            scheduler.spawn_idle(Task::new_idle(idle_pid), hartid);
+        }
 
         plic::init();
         virtio::init();
         println!("[Kernel] Devices Initialized.");
 
-        // [關鍵修正] 使用 Direct Mode (移除 | 1)
-        // 這樣所有的 Trap 都會正確跳轉到 trap_vector 入口
-        core::arch::asm!("csrw mtvec, {}", in(reg) (trap_vector as usize));
-        
-        let first_task = &mut scheduler.tasks[0];
-        core::arch::asm!("csrw mscratch, {}", in(reg) &mut first_task.context);
-        
-        let mstatus: usize = (0 << 11) | (1 << 7) | (1 << 13);
-        core::arch::asm!("csrw mstatus, {}", in(reg) mstatus);
-        
-        timer::set_next();
-        core::arch::asm!("csrrs zero, mie, {}", in(reg) (1 << 11) | (1 << 7));
+        // 核心的 Page Table 與裝置都準備好了，放行停在 Firmware 裡的其他 hart
+        for hart in 0..MAX_HARTS {
+            if hart != hartid && sbi::hart_start(hart, secondary_main as *const () as usize, 0) == sbi::SUCCESS {
+                println!("[Kernel] Releasing hart {}...", hart);
+            }
+        }
 
         println!("[OS] System Ready. Switching to Shell...");
-        
-        core::arch::asm!(
-            "mv sp, {}",
-            "csrw mepc, {}",
-            "mret",
-            in(reg) first_task.context.regs[2],
-            in(reg) first_task.context.mepc
-        );
     }
-    loop {}
+    start_scheduling()
+}
+
+/// 次要 hart 的核心入口 (S-Mode)，由 Firmware 在收到 hart_start 後以 mret 跳進來
+extern "C" fn secondary_main(hartid: usize) -> ! {
+    task::set_hart_id(hartid);
+    unsafe {
+        let satp_val = mm::page_table::KERNEL_SATP;
+        core::arch::asm!("csrw satp, {}", "sfence.vma", in(reg) satp_val);
+    }
+    plic::init_hart();
+    {
+        let mut scheduler = task::SCHEDULER.lock();
+        let idle_pid = scheduler.alloc_pid();
+        scheduler.spawn_idle(Task::new_idle(idle_pid), hartid);
+    }
+    println!("[Kernel] Hart {} started.", hartid);
+    start_scheduling()
+}
+
+/// 每個 hart 共同的最後一步：設定 trap 入口與中斷後進入排程迴圈 (之後一直在開機堆疊上執行)
+fn start_scheduling() -> ! {
+    unsafe {
+        // [關鍵修正] 使用 Direct Mode (移除 | 1)
+        // 這樣所有的 Trap 都會正確跳轉到 trap_vector 入口
+        core::arch::asm!("csrw stvec, {}", in(reg) (trap_vector as *const () as usize));
+        // 開啟 Supervisor External (PLIC) 與 Supervisor Timer 中斷 (sstatus.SIE 由 Task 的 sstatus 決定)
+        core::arch::asm!("csrs sie, {}", in(reg) (1 << 9) | (1 << 5));
+    }
+    task::run_scheduler()
 }
 
 #[panic_handler]
-fn panic(info: &PanicInfo) -> ! { println!("\n[PANIC] {}", info); loop {} }
\ No newline at end of file
+fn panic(info: &PanicInfo) -> ! { sbi::print(format_args!("\n[PANIC] {}\n", info)); loop {} }
\ No newline at end of file
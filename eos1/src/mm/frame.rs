@@ -8,32 +8,98 @@ unsafe extern "C" {
     fn ekernel();
 }
 
+use crate::sync::SpinLock;
+
+const RAM_START: usize = 0x8000_0000;
 const RAM_END: usize = 0x8800_0000;
+const FRAME_COUNT: usize = (RAM_END - RAM_START) / 4096;
+
+struct FrameAllocator {
+    // 每個位元代表一個實體頁面：1 = 已使用 (核心映像檔本身也標成已使用)
+    bitmap: [u64; FRAME_COUNT / 64],
+    // 下一次開始搜尋的位置 (Next Fit)
+    next_index: usize,
+    // 每個實體頁面被多少個 PTE 映射 (COW 共享時會大於 1)
+    ref_count: [u16; FRAME_COUNT],
+}
 
-static mut NEXT_PFN: usize = 0;
+// 所有 hart 共用的實體頁面配置器
+static FRAMES: SpinLock<FrameAllocator> = SpinLock::new(FrameAllocator {
+    bitmap: [0; FRAME_COUNT / 64],
+    next_index: 0,
+    ref_count: [0; FRAME_COUNT],
+});
 
-pub fn init() {
-    unsafe {
-        NEXT_PFN = ekernel as usize;
-        if NEXT_PFN % 4096 != 0 {
-            NEXT_PFN += 4096 - (NEXT_PFN % 4096);
+// MMIO 等不在 RAM 範圍內的位址沒有參考計數
+fn frame_index(paddr: usize) -> Option<usize> {
+    if (RAM_START..RAM_END).contains(&paddr) { Some((paddr - RAM_START) / 4096) } else { None }
+}
+
+impl FrameAllocator {
+    fn is_used(&self, i: usize) -> bool {
+        self.bitmap[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set_used(&mut self, i: usize, used: bool) {
+        if used { self.bitmap[i / 64] |= 1 << (i % 64); } else { self.bitmap[i / 64] &= !(1 << (i % 64)); }
+    }
+
+    /// 從 index 開始標記 count 個頁面為已使用，清成 0 並回傳起始實體位址
+    fn take_frames(&mut self, index: usize, count: usize) -> usize {
+        let paddr = RAM_START + index * 4096;
+        for i in index..index + count {
+            self.set_used(i, true);
+            self.ref_count[i] = 1;
         }
+        self.next_index = (index + count) % FRAME_COUNT;
+        unsafe { core::ptr::write_bytes(paddr as *mut u8, 0, 4096 * count); }
+        paddr
     }
 }
 
-pub fn alloc_frame() -> usize {
-    unsafe {
-        let paddr = NEXT_PFN;
-        let next_paddr = paddr + 4096;
+pub fn init() {
+    let kernel_end = (ekernel as *const () as usize).div_ceil(4096) * 4096;
+    let reserved = (kernel_end - RAM_START) / 4096;
+    let mut frames = FRAMES.lock();
+    for i in 0..reserved { frames.set_used(i, true); }
+    frames.next_index = reserved;
+}
 
-        if next_paddr >= RAM_END {
-            return 0; 
-        }
+pub fn alloc_frame() -> usize {
+    let mut frames = FRAMES.lock();
+    let start = frames.next_index;
+    for offset in 0..FRAME_COUNT {
+        let i = (start + offset) % FRAME_COUNT;
+        if !frames.is_used(i) { return frames.take_frames(i, 1); }
+    }
+    0
+}
 
-        NEXT_PFN = next_paddr;
-        
-        core::ptr::write_bytes(paddr as *mut u8, 0, 4096);
-        
-        paddr
+/// 配置 count 個實體位址連續的頁面 (給需要連續記憶體的裝置使用，例如 VirtIO queue)
+pub fn alloc_contiguous(count: usize) -> usize {
+    let mut frames = FRAMES.lock();
+    let mut run = 0;
+    for i in 0..FRAME_COUNT {
+        run = if frames.is_used(i) { 0 } else { run + 1 };
+        if run == count { return frames.take_frames(i + 1 - count, count); }
     }
-}
\ No newline at end of file
+    0
+}
+
+/// 釋放一個映射對此頁面的參考，最後一個參考消失時才真正歸還頁面
+pub fn dealloc_frame(paddr: usize) {
+    let Some(i) = frame_index(paddr) else { return };
+    let mut frames = FRAMES.lock();
+    if !frames.is_used(i) { panic!("dealloc_frame: double free at {:#x}", paddr); }
+    if frames.ref_count[i] > 0 { frames.ref_count[i] -= 1; }
+    if frames.ref_count[i] == 0 { frames.set_used(i, false); }
+}
+
+/// 多一個映射共享此頁面
+pub fn inc_ref(paddr: usize) {
+    if let Some(i) = frame_index(paddr) { FRAMES.lock().ref_count[i] += 1; }
+}
+
+pub fn ref_count(paddr: usize) -> u16 {
+    frame_index(paddr).map_or(0, |i| FRAMES.lock().ref_count[i])
+}
@@ -1,3 +1,4 @@
 // src/mm/mod.rs
 pub mod frame;
-pub mod page_table; 
\ No newline at end of file
+pub mod page_table;
+pub mod vm;
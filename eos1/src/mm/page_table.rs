@@ -1,19 +1,30 @@
-use super::frame::alloc_frame;
+use super::frame::{self, alloc_frame};
+use crate::sbi;
+use crate::task::hart_id;
+use alloc::vec::Vec;
 
-#[allow(dead_code)]
 pub const PTE_V: usize = 1 << 0;
 pub const PTE_R: usize = 1 << 1;
 pub const PTE_W: usize = 1 << 2;
 pub const PTE_X: usize = 1 << 3;
 pub const PTE_U: usize = 1 << 4;
-#[allow(dead_code)]
 pub const PTE_G: usize = 1 << 5;
-#[allow(dead_code)]
 pub const PTE_A: usize = 1 << 6;
-#[allow(dead_code)]
 pub const PTE_D: usize = 1 << 7;
+// RSW 位元 (硬體忽略)：標記寫入時才複製的共享頁面
+pub const PTE_COW: usize = 1 << 8;
 
 pub static mut KERNEL_PAGE_TABLE: *mut PageTable = core::ptr::null_mut();
+// trap.S 進入核心時切換到這個 satp
+#[unsafe(no_mangle)]
+pub static mut KERNEL_SATP: usize = 0;
+
+// 核心以一個 1GB 的 Superpage (PTE_G) 映射 0x8000_0000 開始的 RAM，
+// 每個使用者 Page Table 都共享這個項目 (沒有 PTE_U，使用者無法存取)
+pub const KERNEL_BASE: usize = 0x8000_0000;
+pub const KERNEL_GIGAPAGE_END: usize = 0xC000_0000;
+// Sv39 的使用者位址只能使用下半部 (bit 38 以上必須為 0)
+pub const USER_SPACE_END: usize = 1 << 38;
 
 #[derive(Clone, Copy)]
 #[repr(transparent)]
@@ -24,6 +35,9 @@ impl PageTableEntry {
     pub fn ppn(&self) -> usize { (self.0 >> 10) & ((1 << 44) - 1) }
     pub fn set_next_table(&mut self, ppn: usize) { self.0 = (ppn << 10) | PTE_V; }
     pub fn set_entry(&mut self, ppn: usize, flags: usize) { self.0 = (ppn << 10) | flags | PTE_V; }
+    pub fn flags(&self) -> usize { self.0 & 0x3FF }
+    // R/W/X 任一位元為 1 代表葉節點，否則指向下一層 Page Table
+    pub fn is_leaf(&self) -> bool { (self.0 & (PTE_R | PTE_W | PTE_X)) != 0 }
 }
 
 #[repr(C, align(4096))]
@@ -31,7 +45,14 @@ pub struct PageTable {
     pub entries: [PageTableEntry; 512],
 }
 
+/// 建立 vaddr → paddr 的映射 (核心開機時使用，缺少中間表格又配置不到頁面時直接 panic)
 pub unsafe fn map(root: &mut PageTable, vaddr: usize, paddr: usize, flags: usize) {
+    if !unsafe { try_map(root, vaddr, paddr, flags) } { panic!("map: out of memory for page tables at {:#x}", vaddr); }
+}
+
+/// 和 map 相同，但配置不到中間的 Page Table 時回傳 false (已經建立的中間表格留在樹中，拆除位址空間時一起釋放)。
+/// 使用者可以觸發的映射 (載入程式、MMAP、brk、堆疊成長) 都必須使用這個版本
+pub unsafe fn try_map(root: &mut PageTable, vaddr: usize, paddr: usize, flags: usize) -> bool {
     let vpn2 = (vaddr >> 30) & 0x1FF;
     let vpn1 = (vaddr >> 21) & 0x1FF;
     let vpn0 = (vaddr >> 12) & 0x1FF;
@@ -39,9 +60,10 @@ pub unsafe fn map(root: &mut PageTable, vaddr: usize, paddr: usize, flags: usize
     let mut pte = &mut root.entries[vpn2];
     let mut next_table: *mut PageTable;
 
+    if pte.is_valid() && pte.is_leaf() { panic!("map: {:#x} is inside a superpage", vaddr); }
     if !pte.is_valid() {
         let frame = alloc_frame();
-        if frame == 0 { panic!("Map OOM L1"); }
+        if frame == 0 { return false; }
         pte.set_next_table(frame >> 12);
     }
     next_table = (pte.ppn() << 12) as *mut PageTable;
@@ -50,7 +72,7 @@ pub unsafe fn map(root: &mut PageTable, vaddr: usize, paddr: usize, flags: usize
     pte = &mut table1.entries[vpn1];
     if !pte.is_valid() {
         let frame = alloc_frame();
-        if frame == 0 { panic!("Map OOM L0"); }
+        if frame == 0 { return false; }
         pte.set_next_table(frame >> 12);
     }
     next_table = (pte.ppn() << 12) as *mut PageTable;
@@ -58,6 +80,7 @@ pub unsafe fn map(root: &mut PageTable, vaddr: usize, paddr: usize, flags: usize
 
     pte = &mut table0.entries[vpn0];
     pte.set_entry(paddr >> 12, flags);
+    true
 }
 
 pub unsafe fn translate(root: &PageTable, vaddr: usize) -> Option<usize> {
@@ -79,21 +102,144 @@ pub unsafe fn translate(root: &PageTable, vaddr: usize) -> Option<usize> {
     Some(pte0.ppn() << 12)
 }
 
+/// 找出 vaddr 對應的葉節點 PTE (不會建立缺少的中間表格)
+pub unsafe fn find_pte(root: &mut PageTable, vaddr: usize) -> Option<&mut PageTableEntry> {
+    let mut table = root;
+    for level in (1..=2).rev() {
+        let pte = &table.entries[(vaddr >> (12 + 9 * level)) & 0x1FF];
+        if !pte.is_valid() || pte.is_leaf() { return None; }
+        table = unsafe { &mut *((pte.ppn() << 12) as *mut PageTable) };
+    }
+    let pte = &mut table.entries[(vaddr >> 12) & 0x1FF];
+    if pte.is_valid() { Some(pte) } else { None }
+}
+
+/// 降級或取消使用者的 PTE 之後清除 TLB。
+/// shared 代表還有其他 Thread 共用這個位址空間 (見 Task::shares_address_space)，它們可能正在其他 hart 上執行、
+/// 保留著舊的 TLB 項目，所以也要請 Firmware 清除其他 hart 的 TLB；回傳之後舊的實體頁面才可以釋放
+pub fn flush_tlb(shared: bool) {
+    unsafe { core::arch::asm!("sfence.vma"); }
+    if shared { sbi::remote_sfence_vma(!(1 << hart_id()), 0); }
+}
+
+/// 建立使用者 Page Table：除了行程自己的映射以外，只共享核心 RAM 的 Superpage，
+/// 讓 trap.S 在切換到核心 Page Table 前後還能執行並存取 Context
 pub unsafe fn new_user_page_table() -> *mut PageTable {
     let root_ptr = alloc_frame() as *mut PageTable;
-    if root_ptr.is_null() { return core::ptr::null_mut(); }
-    
-    // [修正] 包裹在 unsafe 中
+    if root_ptr.is_null() { return root_ptr; }
     let root = unsafe { &mut *root_ptr };
     let kernel_root = unsafe { &*KERNEL_PAGE_TABLE };
-    
     for i in 0..512 {
-        let entry = kernel_root.entries[i];
-        if entry.is_valid() {
-            root.entries[i] = entry;
-        } else {
-            root.entries[i] = PageTableEntry(0);
-        }
+        if kernel_root.entries[i].0 & PTE_G != 0 { root.entries[i] = kernel_root.entries[i]; }
     }
     root_ptr
-}
\ No newline at end of file
+}
+
+/// 列出使用者自己的頁面 (vaddr, PTE)
+pub unsafe fn user_pages(root: &PageTable) -> Vec<(usize, PageTableEntry)> {
+    let mut pages = Vec::new();
+    unsafe { collect_user_pages(root, 2, 0, &mut pages); }
+    pages
+}
+
+unsafe fn collect_user_pages(table: &PageTable, level: usize, base: usize, pages: &mut Vec<(usize, PageTableEntry)>) {
+    for i in 0..512 {
+        let entry = table.entries[i];
+        // PTE_G 是共享的核心映射，不屬於這個行程
+        if !entry.is_valid() || entry.0 & PTE_G != 0 { continue; }
+
+        let vaddr = base | (i << (12 + 9 * level));
+        if entry.is_leaf() {
+            pages.push((vaddr, entry));
+        } else if level > 0 {
+            let next = unsafe { &*((entry.ppn() << 12) as *const PageTable) };
+            unsafe { collect_user_pages(next, level - 1, vaddr, pages); }
+        }
+    }
+}
+
+/// 拆除使用者位址空間：釋放行程的頁面與中間表格，最後釋放 root 本身。
+/// 共享的核心映射 (PTE_G) 不會被釋放；COW 共享的頁面只減少參考計數
+pub unsafe fn free_user_page_table(root: *mut PageTable) {
+    unsafe { free_table(&*root, 2); }
+    frame::dealloc_frame(root as usize);
+}
+
+unsafe fn free_table(table: &PageTable, level: usize) {
+    for i in 0..512 {
+        let entry = table.entries[i];
+        if !entry.is_valid() || entry.0 & PTE_G != 0 { continue; }
+
+        let paddr = entry.ppn() << 12;
+        if !entry.is_leaf() && level > 0 {
+            unsafe { free_table(&*(paddr as *const PageTable), level - 1); }
+        }
+        frame::dealloc_frame(paddr);
+    }
+}
+
+/// fork 用：建立新的 Page Table，和 src 共享所有使用者頁面。
+/// 可寫入的頁面在雙方都改成唯讀 + PTE_COW，等到寫入時才由 handle_cow_fault 複製；
+/// shared 代表父行程還有其他 Thread (見 flush_tlb)
+pub unsafe fn fork_user_page_table(src: &mut PageTable, shared: bool) -> *mut PageTable {
+    let dst_ptr = unsafe { new_user_page_table() };
+    if dst_ptr.is_null() { return core::ptr::null_mut(); }
+    let dst = unsafe { &mut *dst_ptr };
+
+    for (vaddr, pte) in unsafe { user_pages(src) } {
+        let paddr = pte.ppn() << 12;
+        let mut flags = pte.flags() & !PTE_V;
+        if flags & (PTE_W | PTE_COW) != 0 {
+            flags = (flags & !PTE_W) | PTE_COW;
+            if let Some(src_pte) = unsafe { find_pte(src, vaddr) } {
+                src_pte.set_entry(pte.ppn(), flags);
+            }
+        }
+        frame::inc_ref(paddr);
+        if !unsafe { try_map(dst, vaddr, paddr, flags) } {
+            // 已經共享的頁面由 free_user_page_table 減少參考計數；父行程的頁面留在 COW 狀態，寫入時再恢復
+            frame::dealloc_frame(paddr);
+            flush_tlb(shared);
+            unsafe { free_user_page_table(dst_ptr); }
+            return core::ptr::null_mut();
+        }
+    }
+
+    // 父行程的 PTE 權限被降級了，必須清掉 TLB 中的舊項目 (包含其他 hart 上的 Thread)，
+    // 否則它們還能經由舊的項目寫入和子行程共享的頁面
+    flush_tlb(shared);
+    dst_ptr
+}
+
+/// 處理寫入 COW 頁面造成的 Page Fault。
+/// 最後一個持有者直接恢復寫入權限，否則複製出私有頁面。回傳 false 代表不是 COW 頁面；
+/// shared 代表還有其他 Thread 共用這個位址空間 (見 flush_tlb)
+pub unsafe fn handle_cow_fault(root: &mut PageTable, vaddr: usize, shared: bool) -> bool {
+    let pte = match unsafe { find_pte(root, vaddr) } {
+        Some(pte) if pte.flags() & PTE_COW != 0 => pte,
+        // 同一個位址空間的另一個 Thread 已經處理過這一頁，這個 hart 只是還留著唯讀的舊 TLB 項目
+        Some(pte) if pte.flags() & (PTE_U | PTE_W) == PTE_U | PTE_W => {
+            unsafe { core::arch::asm!("sfence.vma"); }
+            return true;
+        }
+        _ => return false,
+    };
+
+    let old_paddr = pte.ppn() << 12;
+    let flags = (pte.flags() & !(PTE_V | PTE_COW)) | PTE_W;
+
+    if frame::ref_count(old_paddr) <= 1 {
+        // 只是放寬權限：其他 hart 的舊項目最多造成一次多餘的 Page Fault (見上面)
+        pte.set_entry(pte.ppn(), flags);
+        unsafe { core::arch::asm!("sfence.vma"); }
+    } else {
+        let new_paddr = alloc_frame();
+        if new_paddr == 0 { return false; }
+        unsafe { core::ptr::copy_nonoverlapping(old_paddr as *const u8, new_paddr as *mut u8, 4096); }
+        pte.set_entry(new_paddr >> 12, flags);
+        // 其他 hart 上的 Thread 必須先改用新的頁面，舊頁面的參考計數才能減少 (減到 0 就會被重新配置)
+        flush_tlb(shared);
+        frame::dealloc_frame(old_paddr);
+    }
+    true
+}
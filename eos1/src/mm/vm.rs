@@ -0,0 +1,228 @@
+// 使用者位址空間中的匿名記憶體：brk 的 Heap、MMAP / MUNMAP 的匿名區段、MPROTECT 修改權限 (動態載入器用來放共用函式庫)，
+// 以及依需要往下長的使用者堆疊。
+// 區段記錄在行程的 VmSpace 中 (同一個行程的 Thread 共用)；某一頁是否已經使用仍然以 Page Table 為準
+use super::page_table::{find_pte, flush_tlb, translate, try_map, PageTable, PTE_COW, PTE_R, PTE_U, PTE_W, PTE_X, USER_SPACE_END};
+use super::frame::{self, alloc_frame};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+// prot 與 flags (與 Linux 相同的編號)
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// MMAP 從這裡開始往上找空位 (在 PIE 與動態載入器的 ASLR 範圍之上)
+pub const MMAP_BASE: usize = 0x30_0000_0000;
+
+/// brk 的 Heap 最多可以成長到多大
+pub const MAX_HEAP_SIZE: usize = 1 << 30;
+
+/// 使用者堆疊：[top - size, top) 在第一次存取時才配置頁面，再往下一頁是永遠不映射的 Guard Page。
+/// size 為 0 代表沒有可以成長的堆疊 (核心 Task)
+#[derive(Clone, Copy, Default)]
+pub struct UserStack {
+    pub top: usize,
+    pub size: usize,
+}
+
+impl UserStack {
+    pub fn bottom(&self) -> usize { self.top - self.size }
+
+    /// 堆疊加上 Guard Page 占用的範圍 (載入程式時 Segment 不能蓋到這裡)
+    pub fn reserved(&self) -> Range<usize> { self.bottom() - 4096..self.top }
+
+    pub fn contains(&self, vaddr: usize) -> bool { (self.bottom()..self.top).contains(&vaddr) }
+
+    /// 存取 Guard Page 代表堆疊已經用完 (stack overflow)
+    pub fn in_guard(&self, vaddr: usize) -> bool { self.size > 0 && (self.bottom() - 4096..self.bottom()).contains(&vaddr) }
+}
+
+/// vaddr 在堆疊的範圍內但還沒有映射時，配置一個全為 0 的頁面給它。回傳 false 代表不是堆疊或記憶體不足
+pub unsafe fn grow_stack(root: &mut PageTable, stack: &UserStack, vaddr: usize) -> bool {
+    if !stack.contains(vaddr) { return false; }
+    let page = vaddr & !0xFFF;
+    if unsafe { translate(root, page) }.is_some() { return false; }
+    let paddr = alloc_frame();
+    if paddr == 0 { return false; }
+    if !unsafe { try_map(root, page, paddr, PTE_U | PTE_R | PTE_W) } {
+        frame::dealloc_frame(paddr);
+        return false;
+    }
+    unsafe { core::arch::asm!("sfence.vma"); }
+    true
+}
+
+/// prot 轉成 PTE 的權限；同時可寫又可執行 (W^X)、沒有任何權限或有不認識的位元時回傳 None
+pub fn prot_flags(prot: usize) -> Option<usize> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 { return None; }
+    if prot & PROT_WRITE != 0 && prot & PROT_EXEC != 0 { return None; }
+    let mut flags = PTE_U;
+    if prot & PROT_READ != 0 { flags |= PTE_R; }
+    // RISC-V 沒有「只可寫」的 PTE，可寫的頁面一定也可讀
+    if prot & PROT_WRITE != 0 { flags |= PTE_R | PTE_W; }
+    if prot & PROT_EXEC != 0 { flags |= PTE_X; }
+    // 沒有任何權限的 PTE 會被硬體當成指向下一層的 Page Table
+    if flags == PTE_U { None } else { Some(flags) }
+}
+
+/// 找出 [MMAP_BASE, USER_SPACE_END) 中第一段連續 pages 頁都沒有映射的位址。
+/// 堆疊還沒配置的部分與 Guard Page 也沒有映射，但要留給堆疊成長，同樣當成已經使用
+unsafe fn find_free(root: &PageTable, stack: &UserStack, pages: usize) -> Option<usize> {
+    let reserved = if stack.size > 0 { stack.reserved() } else { 0..0 };
+    let mut start = MMAP_BASE;
+    let mut run = 0;
+    while run < pages {
+        let vaddr = start + run * 4096;
+        if vaddr >= USER_SPACE_END { return None; }
+        if reserved.contains(&vaddr) {
+            start = reserved.end;
+            run = 0;
+        } else if unsafe { translate(root, vaddr) }.is_some() {
+            start = vaddr + 4096;
+            run = 0;
+        } else {
+            run += 1;
+        }
+    }
+    Some(start)
+}
+
+/// 取消映射並釋放 [start, end) 中的頁面。先清除 TLB (shared 見 flush_tlb) 再釋放，
+/// 其他 hart 上的 Thread 才不會經由舊的項目存取已經交給別人的頁面
+unsafe fn unmap_range(root: &mut PageTable, start: usize, end: usize, shared: bool) {
+    let mut frames = Vec::new();
+    for vaddr in (start..end).step_by(4096) {
+        if let Some(pte) = unsafe { find_pte(root, vaddr) } {
+            frames.push(pte.ppn() << 12);
+            pte.0 = 0;
+        }
+    }
+    flush_tlb(shared);
+    for paddr in frames { frame::dealloc_frame(paddr); }
+}
+
+/// 以全為 0 的新頁面映射 [start, end)。任何一頁已經映射或記憶體不足時回傳 false，不會留下任何頁面
+unsafe fn map_zeroed(root: &mut PageTable, start: usize, end: usize, flags: usize) -> bool {
+    if (start..end).step_by(4096).any(|vaddr| unsafe { translate(root, vaddr) }.is_some()) { return false; }
+    for vaddr in (start..end).step_by(4096) {
+        // alloc_frame 配置的頁面已經清成 0
+        let paddr = alloc_frame();
+        if paddr == 0 || !unsafe { try_map(root, vaddr, paddr, flags) } {
+            if paddr != 0 { frame::dealloc_frame(paddr); }
+            // 還沒有回傳給使用者的頁面，其他 Thread 不會用到
+            unsafe { unmap_range(root, start, vaddr, false); }
+            return false;
+        }
+    }
+    true
+}
+
+/// 配置 len 位元組 (進位到整頁) 內容全為 0 的匿名記憶體，回傳起始位址 (不會落在 stack 保留的範圍)；失敗時不會留下任何頁面
+pub unsafe fn map_anonymous(root: &mut PageTable, stack: &UserStack, len: usize, prot: usize) -> Option<usize> {
+    let flags = prot_flags(prot)?;
+    if len == 0 || len > USER_SPACE_END - MMAP_BASE { return None; }
+    let pages = len.div_ceil(4096);
+    let start = unsafe { find_free(root, stack, pages) }?;
+    if unsafe { map_zeroed(root, start, start + pages * 4096, flags) } { Some(start) } else { None }
+}
+
+/// 修改 [addr, addr + len) 的權限。每一頁都必須是已經映射的使用者頁面，否則整個拒絕、不做任何修改。
+/// fork 後共享的頁面不能直接變成可寫，而是標成 PTE_COW，等到寫入時再複製；shared 見 flush_tlb
+pub unsafe fn protect(root: &mut PageTable, addr: usize, len: usize, prot: usize, shared: bool) -> bool {
+    let Some(flags) = prot_flags(prot) else { return false };
+    let Some(end) = addr.checked_add(len) else { return false };
+    if !addr.is_multiple_of(4096) || end > USER_SPACE_END { return false; }
+    let end = (end + 4095) & !0xFFF;
+
+    for vaddr in (addr..end).step_by(4096) {
+        match unsafe { find_pte(root, vaddr) } {
+            Some(pte) if pte.flags() & PTE_U != 0 => {}
+            _ => return false,
+        }
+    }
+    for vaddr in (addr..end).step_by(4096) {
+        let Some(pte) = (unsafe { find_pte(root, vaddr) }) else { continue };
+        let mut new_flags = flags;
+        if flags & PTE_W != 0 && frame::ref_count(pte.ppn() << 12) > 1 {
+            new_flags = (flags & !PTE_W) | PTE_COW;
+        }
+        pte.set_entry(pte.ppn(), new_flags);
+    }
+
+    // 權限可能被降級了，必須清掉 TLB 中的舊項目 (包含其他 hart)；新的可執行頁面也要讓指令讀取看到最新的內容
+    flush_tlb(shared);
+    unsafe { core::arch::asm!("fence.i"); }
+    true
+}
+
+/// 行程的虛擬記憶體區段：brk 管理的 Heap [heap_start, brk) 與 MMAP 配置的匿名區段 (依位址排序、互不重疊)。
+/// 程式的 Segment 與堆疊不在這裡，所以 MUNMAP 不能拆掉它們。heap_start 為 0 代表沒有 Heap (核心 Task)
+#[derive(Clone, Default)]
+pub struct VmSpace {
+    pub heap_start: usize,
+    pub brk: usize,
+    pub regions: Vec<Range<usize>>,
+}
+
+impl VmSpace {
+    pub fn new(heap_start: usize) -> Self {
+        Self { heap_start, brk: heap_start, regions: Vec::new() }
+    }
+
+    /// 把 program break 移到 new_brk：成長時配置全為 0 的頁面，縮小時釋放不再使用的整頁。
+    /// 回傳新的 break；超出範圍、碰到其他映射或記憶體不足時不做任何修改，回傳原本的 break (與 Linux 的 brk 相同)。
+    /// shared 見 flush_tlb
+    pub unsafe fn set_brk(&mut self, root: &mut PageTable, new_brk: usize, shared: bool) -> usize {
+        if self.heap_start == 0 || new_brk < self.heap_start || new_brk - self.heap_start > MAX_HEAP_SIZE { return self.brk; }
+        let old_end = self.brk.div_ceil(4096) * 4096;
+        let new_end = new_brk.div_ceil(4096) * 4096;
+        if new_end > old_end {
+            if !unsafe { map_zeroed(root, old_end, new_end, PTE_U | PTE_R | PTE_W) } { return self.brk; }
+        } else if new_end < old_end {
+            unsafe { unmap_range(root, new_end, old_end, shared); }
+        }
+        self.brk = new_brk;
+        new_brk
+    }
+
+    /// 配置匿名記憶體 (見 map_anonymous) 並記錄成一個區段
+    pub unsafe fn mmap(&mut self, root: &mut PageTable, stack: &UserStack, len: usize, prot: usize) -> Option<usize> {
+        let start = unsafe { map_anonymous(root, stack, len, prot) }?;
+        let i = self.regions.partition_point(|r| r.start < start);
+        self.regions.insert(i, start..start + len.div_ceil(4096) * 4096);
+        Some(start)
+    }
+
+    /// 取消 [addr, addr + len) 的映射並釋放頁面。整個範圍都必須在 MMAP 配置的區段內 (可以只拆掉區段的一部分)，
+    /// 否則整個拒絕、不做任何修改。shared 見 flush_tlb
+    pub unsafe fn munmap(&mut self, root: &mut PageTable, addr: usize, len: usize, shared: bool) -> bool {
+        let Some(end) = addr.checked_add(len) else { return false };
+        if len == 0 || !addr.is_multiple_of(4096) || end > USER_SPACE_END { return false; }
+        let end = end.div_ceil(4096) * 4096;
+        if !self.covers(addr, end) { return false; }
+
+        unsafe { unmap_range(root, addr, end, shared); }
+        // 與 [addr, end) 重疊的區段只留下兩端剩餘的部分
+        let mut regions = Vec::with_capacity(self.regions.len() + 1);
+        for r in self.regions.drain(..) {
+            if r.end <= addr || end <= r.start { regions.push(r); continue; }
+            if r.start < addr { regions.push(r.start..addr); }
+            if end < r.end { regions.push(end..r.end); }
+        }
+        self.regions = regions;
+        true
+    }
+
+    /// [start, end) 是否完全落在區段內 (可以跨過相鄰的區段)
+    fn covers(&self, start: usize, end: usize) -> bool {
+        let mut cur = start;
+        for r in &self.regions {
+            if r.start > cur { break; }
+            cur = core::cmp::max(cur, r.end);
+            if cur >= end { return true; }
+        }
+        false
+    }
+}
@@ -1,51 +1,77 @@
 use crate::uart;
+use crate::virtio;
+use crate::sync::SpinLock;
+use crate::task::{self, Scheduler, WaitQueue};
 
 pub const BASE: usize = 0x0c00_0000;
 pub const PRIORITY: *mut u32 = BASE as *mut u32;
-pub const ENABLE: *mut u32 = (BASE + 0x2000) as *mut u32;
-pub const THRESHOLD: *mut u32 = (BASE + 0x200000) as *mut u32;
-pub const CLAIM: *mut u32 = (BASE + 0x200004) as *mut u32;
+// 核心在 S-Mode 執行：使用每個 hart 的 S-Mode context (2 * hart + 1)，而不是 M-Mode 的 context 0
+fn context() -> usize { 2 * task::hart_id() + 1 }
+fn enable() -> *mut u32 { (BASE + 0x2000 + 0x80 * context()) as *mut u32 }
+fn threshold() -> *mut u32 { (BASE + 0x200000 + 0x1000 * context()) as *mut u32 }
+fn claim() -> *mut u32 { (BASE + 0x200004 + 0x1000 * context()) as *mut u32 }
+
+const IRQ_UART: u32 = 10;
 
 // 鍵盤緩衝區 (原本在 main.rs)
 const KEY_BUFFER_SIZE: usize = 256;
-static mut KEY_BUFFER: [u8; KEY_BUFFER_SIZE] = [0; KEY_BUFFER_SIZE];
-static mut KEY_HEAD: usize = 0;
-static mut KEY_TAIL: usize = 0;
 
+struct KeyBuffer {
+    buf: [u8; KEY_BUFFER_SIZE],
+    head: usize,
+    tail: usize,
+}
+
+// 任何一個 hart 都可能收到 UART 中斷，也可能在另一個 hart 上被 GETCHAR 讀取
+static KEYS: SpinLock<KeyBuffer> = SpinLock::new(KeyBuffer { buf: [0; KEY_BUFFER_SIZE], head: 0, tail: 0 });
+
+// 等待鍵盤輸入 (GETCHAR) 的 Task (只在持有 SCHEDULER 的鎖時存取)
+pub static mut KEY_WAITERS: WaitQueue = WaitQueue::new();
+
+/// 設定中斷優先權並開啟 UART 接收中斷 (只由開機 hart 呼叫一次)
 pub fn init() {
     unsafe {
         let writer = &raw mut uart::WRITER;
         (*writer).enable_interrupt();
-        let irq_uart = 10; 
-        PRIORITY.add(irq_uart).write_volatile(1);
-        ENABLE.write_volatile(1 << irq_uart);
-        THRESHOLD.write_volatile(0);
+        PRIORITY.add(IRQ_UART as usize).write_volatile(1);
+        PRIORITY.add(virtio::IRQ as usize).write_volatile(1);
     }
+    init_hart();
 }
 
-pub fn push_key(c: u8) {
+/// 讓目前的 hart 接收 UART 與 VirtIO 中斷 (每個 hart 都要呼叫)，由先 claim 到的 hart 處理
+pub fn init_hart() {
     unsafe {
-        let next = (KEY_HEAD + 1) % KEY_BUFFER_SIZE;
-        if next != KEY_TAIL { KEY_BUFFER[KEY_HEAD] = c; KEY_HEAD = next; }
+        enable().write_volatile((1 << IRQ_UART) | (1 << virtio::IRQ));
+        threshold().write_volatile(0);
     }
 }
 
+pub fn push_key(c: u8) {
+    let mut keys = KEYS.lock();
+    let next = (keys.head + 1) % KEY_BUFFER_SIZE;
+    if next != keys.tail { let head = keys.head; keys.buf[head] = c; keys.head = next; }
+}
+
 pub fn pop_key() -> Option<u8> {
-    unsafe {
-        if KEY_HEAD == KEY_TAIL { return None; }
-        let c = KEY_BUFFER[KEY_TAIL];
-        KEY_TAIL = (KEY_TAIL + 1) % KEY_BUFFER_SIZE;
-        Some(c)
-    }
+    let mut keys = KEYS.lock();
+    if keys.head == keys.tail { return None; }
+    let c = keys.buf[keys.tail];
+    keys.tail = (keys.tail + 1) % KEY_BUFFER_SIZE;
+    Some(c)
 }
 
-// 處理 PLIC 中斷的邏輯
-pub fn handle_interrupt() {
+// 處理 PLIC 中斷的邏輯 (claim 到 0 代表中斷已經被其他 hart 處理掉了)
+pub fn handle_interrupt(scheduler: &mut Scheduler) {
     unsafe {
-        let irq = CLAIM.read_volatile();
-        if irq == 10 { 
+        let irq = claim().read_volatile();
+        if irq == IRQ_UART {
             while let Some(c) = uart::_getchar() { push_key(c); }
+            let waiters = &raw mut KEY_WAITERS;
+            scheduler.wake_all(&mut *waiters);
+        } else if irq == virtio::IRQ {
+            virtio::handle_interrupt(scheduler);
         }
-        CLAIM.write_volatile(irq);
+        if irq != 0 { claim().write_volatile(irq); }
     }
 }
\ No newline at end of file
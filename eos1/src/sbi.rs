@@ -0,0 +1,70 @@
+use core::fmt;
+
+// S-Mode 核心呼叫 M-Mode Firmware 的介面 (SBI)
+// 只使用新版的 Extension (EID 都很大)，不會和核心自己的 Syscall 編號 (a7) 衝突
+
+pub const EID_TIME: usize = 0x5449_4D45;
+pub const EID_DBCN: usize = 0x4442_434E;
+pub const EID_HSM: usize = 0x48_534D;
+pub const EID_RFENCE: usize = 0x5246_4E43;
+
+pub const TIME_SET_TIMER: usize = 0;
+pub const DBCN_WRITE_BYTE: usize = 2;
+pub const HSM_HART_START: usize = 0;
+pub const RFENCE_SFENCE_VMA: usize = 1;
+
+pub const SUCCESS: isize = 0;
+pub const ERR_NOT_SUPPORTED: isize = -2;
+pub const ERR_INVALID_PARAM: isize = -3;
+pub const ERR_ALREADY_AVAILABLE: isize = -6;
+
+fn sbi_call(eid: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> (isize, usize) {
+    let error: isize;
+    let value: usize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            in("a7") eid,
+            in("a6") fid,
+            inlateout("a0") arg0 => error,
+            inlateout("a1") arg1 => value,
+            in("a2") arg2,
+        );
+    }
+    (error, value)
+}
+
+/// 在 time >= stime 時觸發 Supervisor Timer 中斷 (同時清除目前待處理的 Timer 中斷)
+pub fn set_timer(stime: u64) {
+    sbi_call(EID_TIME, TIME_SET_TIMER, stime as usize, 0, 0);
+}
+
+/// 透過 Firmware 輸出一個字元：不經過核心的 Page Table，panic 時也能使用
+pub fn console_putchar(c: u8) {
+    sbi_call(EID_DBCN, DBCN_WRITE_BYTE, c as usize, 0, 0);
+}
+
+/// 讓停在 Firmware 裡的 hart 以 S-Mode 從 start_addr 開始執行 (a0 = hartid、a1 = opaque)
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> isize {
+    sbi_call(EID_HSM, HSM_HART_START, hartid, start_addr, opaque).0
+}
+
+/// 讓 hart_mask (從 hart_mask_base 開始，base 為 usize::MAX 代表全部) 中的 hart 清除 TLB，
+/// 回傳時它們都已經執行過 sfence.vma。Firmware 一律清除整個 TLB，所以不傳 start_addr / size
+pub fn remote_sfence_vma(hart_mask: usize, hart_mask_base: usize) -> isize {
+    sbi_call(EID_RFENCE, RFENCE_SFENCE_VMA, hart_mask, hart_mask_base, 0).0
+}
+
+struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() { console_putchar(byte); }
+        Ok(())
+    }
+}
+
+pub fn print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    let _ = Console.write_fmt(args);
+}
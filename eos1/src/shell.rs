@@ -1,5 +1,6 @@
 // === FILE: ./eos1/src/shell.rs ===
 use crate::syscall::*; 
+use crate::exec::ExecError;
 use alloc::vec::Vec;
 use alloc::string::String;
 use core::fmt;
@@ -34,16 +35,21 @@ fn sys_file_list(index: usize, buf: &mut [u8]) -> isize {
     ret 
 }
 
-fn sys_exec(data: &[u8], argv: &[&str]) -> isize { 
+// 核心以 C 字串 (結尾補 0) 與以 NULL 結尾的指標陣列接收參數
+fn sys_spawn(path: &str, argv: &[&str], envp: &[String]) -> isize { 
+    let path_c = c_string(path);
+    let args_c: Vec<Vec<u8>> = argv.iter().map(|a| c_string(a)).collect();
+    let envs_c: Vec<Vec<u8>> = envp.iter().map(|e| c_string(e)).collect();
+    let arg_ptrs = c_string_array(&args_c);
+    let env_ptrs = c_string_array(&envs_c);
     let mut ret: isize; 
     unsafe { 
         core::arch::asm!(
             "ecall", 
-            in("a7") EXEC, 
-            in("a0") data.as_ptr(), 
-            in("a1") data.len(), 
-            in("a2") argv.as_ptr(), 
-            in("a3") argv.len(), 
+            in("a7") SPAWN, 
+            in("a0") path_c.as_ptr(), 
+            in("a1") arg_ptrs.as_ptr(), 
+            in("a2") env_ptrs.as_ptr(),
             lateout("a0") ret
         ); 
     } 
@@ -85,20 +91,22 @@ fn sys_chdir(name: &str) -> isize {
 }
 
 // [新增] Yield: 主動讓出 CPU
+#[allow(dead_code)]
 fn sys_yield() { 
     unsafe { core::arch::asm!("ecall", in("a7") SCHED_YIELD); } 
 }
 
-// [新增] Wait: 等待子行程結束
-// 回傳值: >0 (子行程 PID), -1 (子行程仍在執行), -2 (無子行程)
-fn sys_wait(status: &mut i32) -> isize {
+// [新增] Wait: 等待子行程結束 (pid == -1 代表任意子行程)
+// 回傳值: >0 (子行程 PID), 0 (WNOHANG 且子行程仍在執行), -1 (沒有符合的子行程)
+fn sys_waitpid(pid: isize, status: &mut i32, options: u64) -> isize {
     let mut ret: isize;
     unsafe { 
         core::arch::asm!(
             "ecall", 
             in("a7") WAIT, 
-            in("a0") -1, // 等待任意子行程
+            in("a0") pid,
             in("a1") status as *mut i32, 
+            in("a2") options,
             lateout("a0") ret
         ); 
     }
@@ -148,6 +156,60 @@ fn parse_int(s: &str) -> Option<u64> {
     Some(res)
 }
 
+// 回報子行程的結束方式 (status 編碼見 task::exit_status)
+fn report_status(pid: isize, status: i32) {
+    let signal = status & 0x7f;
+    let code = (status >> 8) & 0xff;
+    if signal != 0 {
+        let name = match signal {
+            crate::task::SIGILL => "SIGILL",
+            crate::task::SIGTRAP => "SIGTRAP",
+            crate::task::SIGBUS => "SIGBUS",
+            crate::task::SIGSEGV => "SIGSEGV",
+            _ => "?",
+        };
+        user_println!("[{}] killed by signal {} ({})", pid, signal, name);
+    } else if code != 0 {
+        user_println!("[{}] exited with code {}", pid, code);
+    }
+}
+
+fn c_string(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(s.len() + 1);
+    bytes.extend_from_slice(s.as_bytes());
+    bytes.push(0);
+    bytes
+}
+
+fn c_string_array(strings: &[Vec<u8>]) -> Vec<*const u8> {
+    let mut ptrs: Vec<*const u8> = strings.iter().map(|s| s.as_ptr()).collect();
+    ptrs.push(core::ptr::null());
+    ptrs
+}
+
+// 執行程式時預設的搜尋路徑 (以 : 分隔) 與家目錄
+const PATH: &str = "/";
+const HOME: &str = "/";
+
+// 傳給子行程的環境變數
+fn child_env(pwd: &str) -> Vec<String> {
+    vec![alloc::format!("PATH={}", PATH), alloc::format!("HOME={}", HOME), alloc::format!("PWD={}", pwd)]
+}
+
+// 名稱中沒有 / 時，先找目前目錄，再依序找 PATH 中的每個目錄
+// 找到檔案但無法執行 (例如不是合法的 ELF) 時直接回傳那個錯誤碼
+fn spawn_program(name: &str, argv: &[&str], envp: &[String]) -> isize {
+    let not_found = ExecError::NotFound.code();
+    let pid = sys_spawn(name, argv, envp);
+    if pid != not_found || name.contains('/') { return pid; }
+    for dir in PATH.split(':').filter(|d| !d.is_empty()) {
+        let path = alloc::format!("{}/{}", dir.trim_end_matches('/'), name);
+        let pid = sys_spawn(&path, argv, envp);
+        if pid != not_found { return pid; }
+    }
+    not_found
+}
+
 // 支援引號的參數解析器
 fn parse_args(input: &str) -> Vec<String> {
     let mut args = Vec::new();
@@ -177,6 +239,8 @@ fn parse_args(input: &str) -> Vec<String> {
 pub extern "C" fn shell_entry() -> ! {
     user_println!("Shell initialized (Sync Mode).");
     let mut command = String::new();
+    // 目前的工作目錄 (核心只記錄目錄所在的 Sector，路徑由 shell 自己追蹤)
+    let mut pwd = String::from("/");
     user_print!("eos> ");
 
     loop {
@@ -223,7 +287,14 @@ pub extern "C" fn shell_entry() -> ! {
                             if parts.len() < 2 { user_println!("Usage: cd <dir>"); }
                             else {
                                 let ret = sys_chdir(&parts[1]);
-                                if ret == 0 { user_println!("Changed directory."); }
+                                if ret == 0 {
+                                    if parts[1] == "/" { pwd = String::from("/"); }
+                                    else {
+                                        if !pwd.ends_with('/') { pwd.push('/'); }
+                                        pwd.push_str(&parts[1]);
+                                    }
+                                    user_println!("Changed directory.");
+                                }
                                 else { user_println!("Directory not found."); }
                             }
                         },
@@ -243,37 +314,18 @@ pub extern "C" fn shell_entry() -> ! {
                         "exec" => {
                             if parts.len() < 2 { user_println!("Usage: exec <file> [args...]"); }
                             else {
-                                let fname = &parts[1];
-                                let len = sys_file_len(fname);
-                                if len < 0 { user_println!("File not found."); }
-                                else {
-                                    let mut elf_data = vec![0u8; len as usize];
-                                    sys_file_read(fname, &mut elf_data);
-                                    
-                                    let args_vec: Vec<&str> = parts[1..].iter().map(|s| s.as_str()).collect();
-                                    
-                                    // 1. 建立並執行子行程
-                                    let pid = sys_exec(&elf_data, &args_vec);
-                                    
-                                    if pid > 0 {
-                                        // 2. 同步等待子行程結束
-                                        let mut status = 0;
-                                        loop {
-                                            let wpid = sys_wait(&mut status);
-                                            if wpid > 0 {
-                                                // 子行程已結束並被回收
-                                                break;
-                                            } else if wpid == -1 {
-                                                // 子行程仍在執行，Shell 主動讓出 CPU
-                                                sys_yield();
-                                            } else {
-                                                // 無子行程 (理論上不應發生)
-                                                break;
-                                            }
-                                        }
-                                    } else {
-                                        user_println!("Exec failed.");
-                                    }
+                                let args_vec: Vec<&str> = parts[1..].iter().map(|s| s.as_str()).collect();
+                                
+                                // 1. 建立並執行子行程 (核心直接從檔案系統載入)
+                                let pid = spawn_program(&parts[1], &args_vec, &child_env(&pwd));
+                                
+                                if pid > 0 {
+                                    // 2. 同步等待子行程結束 (由核心負責阻塞)
+                                    let mut status = 0;
+                                    if sys_waitpid(pid, &mut status, 0) == pid { report_status(pid, status); }
+                                } else {
+                                    let reason = ExecError::from_code(pid).map_or("unknown error", |e| e.description());
+                                    user_println!("Exec failed: {} ({})", reason, pid);
                                 }
                             }
                         },
@@ -311,10 +363,14 @@ pub extern "C" fn shell_entry() -> ! {
                 command.push(c as char); 
             }
         }
-        for _ in 0..1000 {}
     }
 }
 
-pub extern "C" fn bg_task() -> ! {
-    loop { for _ in 0..5000000 {} }
-}
\ No newline at end of file
+// PID 1：回收孤兒行程 (父行程先結束的子行程會被交給 init)
+// 沒有子行程時 WAIT 也會讓 init 睡眠，直到有孤兒被交給它
+pub extern "C" fn init_entry() -> ! {
+    let mut status = 0;
+    loop {
+        sys_waitpid(-1, &mut status, 0);
+    }
+}
@@ -3,6 +3,8 @@ use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicBool, Ordering};
 
+const SSTATUS_SIE: usize = 1 << 1;
+
 pub struct SpinLock<T> {
     lock: AtomicBool,
     data: UnsafeCell<T>,
@@ -19,30 +21,48 @@ impl<T> SpinLock<T> {
         }
     }
 
-    pub fn lock(&self) -> SpinLockGuard<T> {
+    /// 持有鎖的期間關閉這個 hart 的中斷：
+    /// 否則中斷處理程式再拿同一把鎖 (例如 heap) 就會在同一個 hart 上自己卡死
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        let sstatus: usize;
+        unsafe { core::arch::asm!("csrrc {}, sstatus, {}", out(reg) sstatus, in(reg) SSTATUS_SIE); }
         while self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
             core::hint::spin_loop();
         }
-        SpinLockGuard { lock: &self.lock, data: unsafe { &mut *self.data.get() } }
+        SpinLockGuard {
+            lock: &self.lock,
+            data: unsafe { &mut *self.data.get() },
+            interrupts_enabled: sstatus & SSTATUS_SIE != 0,
+        }
+    }
+
+    /// 接手另一個執行流程 (切換 Task 前) 沒有放開的鎖，放開時不會打開中斷。
+    /// 呼叫者必須確定鎖正被交給自己，例如排程器在 switch_to 前後的交接
+    pub unsafe fn inherit(&self) -> SpinLockGuard<'_, T> {
+        SpinLockGuard { lock: &self.lock, data: unsafe { &mut *self.data.get() }, interrupts_enabled: false }
     }
 }
 
 pub struct SpinLockGuard<'a, T> {
     lock: &'a AtomicBool,
     data: &'a mut T,
+    interrupts_enabled: bool, // 上鎖前的 sstatus.SIE
 }
 
-impl<'a, T> Deref for SpinLockGuard<'a, T> {
+impl<T> Deref for SpinLockGuard<'_, T> {
     type Target = T;
     fn deref(&self) -> &Self::Target { self.data }
 }
 
-impl<'a, T> DerefMut for SpinLockGuard<'a, T> {
+impl<T> DerefMut for SpinLockGuard<'_, T> {
     fn deref_mut(&mut self) -> &mut Self::Target { self.data }
 }
 
-impl<'a, T> Drop for SpinLockGuard<'a, T> {
+impl<T> Drop for SpinLockGuard<'_, T> {
     fn drop(&mut self) {
         self.lock.store(false, Ordering::Release);
+        if self.interrupts_enabled {
+            unsafe { core::arch::asm!("csrs sstatus, {}", in(reg) SSTATUS_SIE); }
+        }
     }
-}
\ No newline at end of file
+}
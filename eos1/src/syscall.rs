@@ -1,11 +1,19 @@
 // === FILE: ./eos1/src/syscall.rs ===
-use crate::task::{self, Task, TaskState};
-use crate::mm::page_table::{new_user_page_table, PTE_U, PTE_R, PTE_W, translate};
-use crate::mm::{frame, page_table};
+use crate::task::{self, SchedulerGuard, Task, TaskState, new_vm_table};
+use crate::mm::page_table::{fork_user_page_table, free_user_page_table, handle_cow_fault, find_pte, PTE_U, PTE_R, PTE_W};
+use crate::mm::page_table;
+use crate::mm::vm;
 use crate::fs;
-use crate::elf;
+use crate::exec::{self, ExecError};
+use crate::fpu;
 use crate::plic;
+use crate::virtio;
+use crate::timer;
+use crate::sync::SpinLock;
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::mem::size_of;
 
 pub const PUTCHAR: u64 = 1;
 pub const GETCHAR: u64 = 2;
@@ -14,43 +22,238 @@ pub const FILE_READ: u64 = 4;
 pub const FILE_LIST: u64 = 5;
 pub const FILE_WRITE: u64 = 8; 
 pub const CHDIR: u64 = 9;
-pub const EXEC: u64 = 6;
+pub const SPAWN: u64 = 6;
 pub const DISK_READ: u64 = 7;
+pub const SLEEP_MS: u64 = 10;
+pub const CPU_STAT: u64 = 11;
+pub const THREAD_CREATE: u64 = 12;
+pub const FUTEX: u64 = 98;
+pub const NANOSLEEP: u64 = 101;
 pub const EXIT: u64 = 93;
 pub const SCHED_YIELD: u64 = 124;
+pub const SETPRIORITY: u64 = 140;
+pub const GETPRIORITY: u64 = 141;
 pub const GETPID: u64 = 172;
+pub const FORK: u64 = 220;
+pub const EXECVE: u64 = 221;
+pub const BRK: u64 = 214;
+pub const MUNMAP: u64 = 215;
+pub const MMAP: u64 = 222;
+pub const MPROTECT: u64 = 226;
 pub const WAIT: u64 = 260; 
 
-unsafe fn user_to_kernel_ptr<T>(vaddr: usize, current_task: &Task) -> Option<*mut T> {
-    if vaddr >= 0x8000_0000 && vaddr < 0x8800_0000 { return Some(vaddr as *mut T); }
-    let root_ptr = (current_task.root_ppn << 12) as *const page_table::PageTable;
-    if root_ptr.is_null() { return None; }
-    let root = unsafe { &*root_ptr };
-    if let Some(paddr) = unsafe { translate(root, vaddr) } {
-        let offset = vaddr & 0xFFF;
-        Some((paddr + offset) as *mut T)
-    } else { None }
+// WAIT 的 options：子行程都還在執行時立即回傳 0，而不是等待
+pub const WNOHANG: u64 = 1;
+
+// SETPRIORITY 沒有權限時的回傳值 (-1 代表沒有這個 Task 或參數錯誤)
+pub const EPERM: u64 = (-2isize) as u64;
+
+// FUTEX 的 op (與 Linux 相同的編號)
+pub const FUTEX_WAIT: u64 = 0;
+pub const FUTEX_WAKE: u64 = 1;
+
+/// 把使用者位址 [vaddr, vaddr + len) 逐頁轉成實體位址，回傳每一段的 (實體位址, 長度)。
+/// 每一頁都必須是這個行程自己的使用者頁面 (PTE_U)，所以指向核心記憶體的指標一律被拒絕；
+/// need_write 時頁面還必須可寫，COW 頁面會先複製成私有頁面，
+/// 否則核心直接寫入實體位址時會改到和其他行程共享的頁面；堆疊中還沒有配置的頁面則先配置 (和 Page Fault 相同)
+unsafe fn user_chunks(vaddr: usize, len: usize, need_write: bool, current_task: &Task) -> Option<Vec<(usize, usize)>> {
+    let end = vaddr.checked_add(len)?;
+
+    // 核心 Task 沒有自己的 Page Table，傳進來的是核心映像檔中的位址
+    if current_task.root_ppn == 0 {
+        if vaddr < 0x8000_0000 || end > 0x8800_0000 { return None; }
+        return Some(vec![(vaddr, len)]);
+    }
+
+    let root = unsafe { &mut *((current_task.root_ppn << 12) as *mut page_table::PageTable) };
+    let mut chunks = Vec::new();
+    let mut cur = vaddr;
+    while cur < end {
+        let page = cur & !0xFFF;
+        unsafe { vm::grow_stack(root, &current_task.user_stack, page); }
+        if need_write { unsafe { handle_cow_fault(root, page, current_task.shares_address_space()); } }
+        let pte = unsafe { find_pte(root, page) }?;
+        let required = PTE_U | if need_write { PTE_W } else { PTE_R };
+        if pte.flags() & required != required { return None; }
+
+        let chunk_len = core::cmp::min(end, page + 4096) - cur;
+        chunks.push(((pte.ppn() << 12) + (cur & 0xFFF), chunk_len));
+        cur += chunk_len;
+    }
+    Some(chunks)
+}
+
+/// 檔名與路徑 (包含 C 字串) 的長度上限
+const MAX_PATH_LEN: usize = 4096;
+
+/// 從使用者空間複製 len 個位元組到核心 (可跨頁)。
+/// len 超過 fs::MAX_FILE_SIZE (任何 Syscall 需要複製的最大量) 就直接拒絕，
+/// 並且先確認整個範圍都有效才配置核心的緩衝區，使用者無法用很大的 len 讓核心配置失敗而 panic
+unsafe fn copy_from_user(vaddr: usize, len: usize, current_task: &Task) -> Option<Vec<u8>> {
+    if len > fs::MAX_FILE_SIZE { return None; }
+    let chunks = unsafe { user_chunks(vaddr, len, false, current_task) }?;
+    let mut data = Vec::with_capacity(len);
+    for (paddr, chunk_len) in chunks {
+        data.extend_from_slice(unsafe { core::slice::from_raw_parts(paddr as *const u8, chunk_len) });
+    }
+    Some(data)
 }
 
-pub unsafe fn dispatcher(ctx: &mut crate::task::Context) -> *mut crate::task::Context {
+/// 把 data 複製到使用者空間 (可跨頁)；任何一頁無效就整個拒絕，不會寫入一半
+unsafe fn copy_to_user(vaddr: usize, data: &[u8], current_task: &Task) -> bool {
+    let Some(chunks) = (unsafe { user_chunks(vaddr, data.len(), true, current_task) }) else { return false };
+    let mut offset = 0;
+    for (paddr, chunk_len) in chunks {
+        unsafe { core::ptr::copy_nonoverlapping(data[offset..].as_ptr(), paddr as *mut u8, chunk_len); }
+        offset += chunk_len;
+    }
+    true
+}
+
+unsafe fn read_user<T: Copy>(vaddr: usize, current_task: &Task) -> Option<T> {
+    let data = unsafe { copy_from_user(vaddr, size_of::<T>(), current_task) }?;
+    Some(unsafe { core::ptr::read_unaligned(data.as_ptr() as *const T) })
+}
+
+unsafe fn write_user<T: Copy>(vaddr: usize, value: &T, current_task: &Task) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) };
+    unsafe { copy_to_user(vaddr, bytes, current_task) }
+}
+
+/// 讀取以 0 結尾的 C 字串 (不含結尾的 0)，超過 MAX_PATH_LEN 位元組視為無效
+unsafe fn user_cstr(vaddr: usize, current_task: &Task) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut cur = vaddr;
+    // 一次讀到頁尾，才不會碰到字串之後可能沒有映射的頁面
+    while bytes.len() < MAX_PATH_LEN {
+        let chunk = unsafe { copy_from_user(cur, 4096 - (cur & 0xFFF), current_task) }?;
+        if let Some(end) = chunk.iter().position(|&b| b == 0) {
+            bytes.extend_from_slice(&chunk[..end]);
+            return Some(bytes);
+        }
+        bytes.extend_from_slice(&chunk);
+        cur += chunk.len();
+    }
+    None
+}
+
+/// 讀取以 NULL 結尾的字串指標陣列 (argv / envp)；陣列本身是 NULL 時視為空陣列
+unsafe fn user_cstr_array(vaddr: usize, current_task: &Task) -> Result<Vec<Vec<u8>>, ExecError> {
+    let mut strings = Vec::new();
+    if vaddr == 0 { return Ok(strings); }
+    loop {
+        let entry = vaddr.checked_add(strings.len() * 8).ok_or(ExecError::BadAddress)?;
+        let ptr = unsafe { read_user::<usize>(entry, current_task) }.ok_or(ExecError::BadAddress)?;
+        if ptr == 0 { return Ok(strings); }
+        if strings.len() == exec::MAX_ARGS { return Err(ExecError::TooBig); }
+        strings.push(unsafe { user_cstr(ptr, current_task) }.ok_or(ExecError::BadAddress)?);
+    }
+}
+
+/// SPAWN / EXECVE 的參數：(path, argv, envp)，都是 C 字串
+type ExecArgs = (String, Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+unsafe fn user_exec_args(path: usize, argv: usize, envp: usize, current_task: &Task) -> Result<ExecArgs, ExecError> {
+    let path = unsafe { user_cstr(path, current_task) }.ok_or(ExecError::BadAddress)?;
+    // 檔名不是合法的 UTF-8 就不可能存在
+    let path = String::from_utf8(path).map_err(|_| ExecError::NotFound)?;
+    Ok((path, unsafe { user_cstr_array(argv, current_task) }?, unsafe { user_cstr_array(envp, current_task) }?))
+}
+
+/// 讀取使用者傳入的檔名 (不是合法 UTF-8 時視為空字串，和原本的行為相同)；超過 MAX_PATH_LEN 時回傳 None
+unsafe fn user_str(vaddr: usize, len: usize, current_task: &Task) -> Option<String> {
+    if len > MAX_PATH_LEN { return None; }
+    let bytes = unsafe { copy_from_user(vaddr, len, current_task) }?;
+    Some(String::from_utf8(bytes).unwrap_or_default())
+}
+
+// nanosleep 的時間參數 (與 struct timespec 相同的配置)
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+/// Syscall 在目前 Task 的核心堆疊上執行：需要等待時在 WaitQueue 上睡眠並呼叫 task::sched，
+/// 醒來後直接在這裡繼續，最後一律推進 sepc 回到 ecall 的下一道指令
+pub unsafe fn dispatcher(ctx: &mut crate::task::Context, mut scheduler: SchedulerGuard) {
     let id = ctx.regs[17];
     let a0 = ctx.regs[10];
     let a1 = ctx.regs[11];
     let a2 = ctx.regs[12];
     let a3 = ctx.regs[13];
 
-    let scheduler = task::get_scheduler();
-
     match id {
         PUTCHAR => print!("{}", a0 as u8 as char),
-        GETCHAR => ctx.regs[10] = plic::pop_key().unwrap_or(0) as u64,
+        GETCHAR => {
+            // 沒有輸入：在鍵盤等待佇列上睡眠，被 PLIC 中斷喚醒後再檢查一次
+            // (pop_key 與 block_current 都在持有排程器的鎖時進行，不會錯過喚醒)
+            let c = loop {
+                if let Some(c) = plic::pop_key() { break c; }
+                let waiters = &raw mut plic::KEY_WAITERS;
+                scheduler.block_current(unsafe { &mut *waiters });
+                scheduler = task::sched(scheduler);
+            };
+            ctx.regs[10] = c as u64;
+        },
         
         SCHED_YIELD => {
-            // [關鍵修正] 
-            // 因為我們要切換 Context，不會執行函式底部的 `ctx.mepc += 4`
-            // 所以必須在這裡手動推進 PC，否則下次醒來會再次執行 ecall (無限 Yield)
-            ctx.mepc += 4; 
-            return unsafe { scheduler.schedule() };
+            task::sched(scheduler);
+        },
+
+        SLEEP_MS | NANOSLEEP => {
+            // 時間太長 (換算成 tick 或加上現在的時間會溢位) 或 tv_nsec 不在 0..1_000_000_000 時回傳 -1
+            let ticks = if id == SLEEP_MS {
+                a0.checked_mul(timer::TICKS_PER_SEC / 1000)
+            } else {
+                unsafe { read_user::<TimeSpec>(a0 as usize, scheduler.current_task()) }
+                    .filter(|ts| ts.tv_sec >= 0 && (0..1_000_000_000).contains(&ts.tv_nsec))
+                    .and_then(|ts| (ts.tv_sec as u64).checked_mul(timer::TICKS_PER_SEC)?
+                        .checked_add(ts.tv_nsec as u64 / (1_000_000_000 / timer::TICKS_PER_SEC)))
+            };
+            match ticks.and_then(|ticks| timer::now().checked_add(ticks)) {
+                Some(deadline) => {
+                    scheduler.sleep_current(deadline);
+                    task::sched(scheduler);
+                    ctx.regs[10] = 0;
+                }
+                None => ctx.regs[10] = (-1isize) as u64,
+            }
+        },
+
+        CPU_STAT => {
+            // cpu_stat(hart, *mut CpuStat)：給 top 之類的工具計算 CPU 使用率
+            let stat = scheduler.cpu_stat(a0 as usize);
+            let current_task = scheduler.current_task();
+            ctx.regs[10] = match stat {
+                Some(stat) if unsafe { write_user(a1 as usize, &stat, current_task) } => 0,
+                _ => (-1isize) as u64,
+            };
+        },
+
+        SETPRIORITY => {
+            // setpriority(pid, priority)：0 最高，task::MLFQ_LEVELS - 1 最低。
+            // 只能修改自己、自己的後代或同一個行程的 Thread；提高優先權 (數字變小) 只有核心 Task 可以。
+            // 回傳 0、-1 (沒有這個 Task 或優先權超出範圍) 或 EPERM (沒有權限)
+            let (pid, priority) = (a0 as usize, a1 as usize);
+            let caller = scheduler.current_task();
+            let (caller_id, caller_root, privileged) = (caller.id, caller.root_ppn, caller.root_ppn == 0);
+            ctx.regs[10] = match scheduler.find(pid).map(|t| (t.priority, t.root_ppn)) {
+                None => (-1isize) as u64,
+                Some((current, root_ppn)) => {
+                    let owned = privileged || scheduler.is_descendant(pid, caller_id) || root_ppn == caller_root;
+                    if !owned || (priority < current && !privileged) { EPERM }
+                    else if scheduler.set_priority(pid, priority) { 0 } else { (-1isize) as u64 }
+                }
+            };
+        },
+
+        GETPRIORITY => {
+            ctx.regs[10] = match scheduler.find(a0 as usize) {
+                Some(t) => t.priority as u64,
+                None => (-1isize) as u64,
+            };
         },
 
         GETPID => {
@@ -58,173 +261,347 @@ pub unsafe fn dispatcher(ctx: &mut crate::task::Context) -> *mut crate::task::Co
         },
 
         EXIT => {
-            let exit_code = a0 as i32;
+            // 正常結束：exit code 放在 status 的 bit 8..15 (與 WEXITSTATUS 相容)
+            scheduler.exit_current(task::exit_status(a0 as i32));
+            // 這個 Task 不會再被選中了
+            task::sched(scheduler);
+            unreachable!("zombie task resumed");
+        },
+
+        FORK => {
+            let parent = scheduler.current_task();
+            // 核心 Task (root_ppn == 0) 的堆疊在核心 Heap 中，無法複製位址空間
+            if parent.root_ppn == 0 { ctx.regs[10] = (-1isize) as u64; }
+            else {
+                let parent_root = unsafe { &mut *((parent.root_ppn << 12) as *mut page_table::PageTable) };
+                let child_table = unsafe { fork_user_page_table(parent_root, parent.shares_address_space()) };
+                if child_table.is_null() { ctx.regs[10] = (-1isize) as u64; }
+                else {
+                    // 子行程有自己的檔案表 (Thread 才共用)
+                    let files = Arc::new(SpinLock::new(parent.files.lock().clone()));
+                    let vm = Arc::new(SpinLock::new(parent.vm.lock().clone()));
+                    let cwd = parent.cwd;
+                    let user_stack = parent.user_stack;
+                    let parent_id = parent.id;
+                    let child_pid = scheduler.alloc_pid();
+                    let mut child = Task::new_user(child_pid);
+                    child.parent_id = parent_id;
+                    child.root_ppn = (child_table as usize) >> 12;
+                    child.user_stack = user_stack;
+                    child.files = files;
+                    child.vm = vm;
+                    child.cwd = cwd;
+                    // 子行程從 ecall 的下一道指令繼續執行，且 fork 回傳 0
+                    child.context = *ctx;
+                    child.context.regs[10] = 0;
+                    child.context.sepc += 4;
+                    scheduler.spawn(child);
+                    ctx.regs[10] = child_pid as u64;
+                }
+            }
+        },
+
+        THREAD_CREATE => {
+            // thread_create(entry, stack_top, arg, tls)：與呼叫者共用位址空間與檔案表的新 Task。
+            // 從 entry 開始執行，a0 = arg、sp = stack_top、tp = tls (給 TLS 使用)；
+            // 結束時呼叫 EXIT，同一個行程的 Task 可以用 WAIT 指定 tid 等它 (join)；pid == -1 的 WAIT 不會回收 Thread
+            let parent = scheduler.current_task();
+            if parent.root_ppn == 0 { ctx.regs[10] = (-1isize) as u64; }
+            else {
+                let root_ppn = parent.root_ppn;
+                let user_stack = parent.user_stack;
+                let files = Arc::clone(&parent.files);
+                let vm = Arc::clone(&parent.vm);
+                let cwd = parent.cwd;
+                let parent_id = parent.id;
+                let tid = scheduler.alloc_pid();
+                let mut thread = Task::new_user(tid);
+                thread.parent_id = parent_id;
+                thread.is_thread = true;
+                thread.root_ppn = root_ppn;
+                // Thread 自己的堆疊由建立者配置，主程式的堆疊仍然可以在任何 Thread 存取時成長
+                thread.user_stack = user_stack;
+                thread.files = files;
+                thread.vm = vm;
+                thread.cwd = cwd;
+                thread.context.sepc = a0;
+                thread.context.regs[2] = a1 & !0xF;
+                thread.context.regs[10] = a2;
+                thread.context.regs[4] = a3;
+                scheduler.spawn(thread);
+                ctx.regs[10] = tid as u64;
+            }
+        },
+
+        FUTEX => {
+            // futex(uaddr, op, val)
+            // FUTEX_WAIT：*uaddr 仍等於 val 才睡眠，否則立即回傳 -1 (呼叫者應重新檢查)
+            // FUTEX_WAKE：喚醒最多 val 個在 uaddr 上等待的 Task，回傳喚醒的數量
+            let uaddr = a0 as usize;
             let current = scheduler.current_task();
-            current.state = TaskState::Zombie;
-            current.exit_code = exit_code;
-            // EXIT 不需要 +=4，因為這個 Task 不會再醒來了
-            return unsafe { scheduler.schedule() };
+            let key = (current.root_ppn, uaddr);
+            let queues = &raw mut task::FUTEX_WAITERS;
+            ctx.regs[10] = if !uaddr.is_multiple_of(4) { (-1isize) as u64 } else {
+                match a1 {
+                    // 比較與睡眠都在持有排程器的鎖時進行：FUTEX_WAKE 不會在這中間發生，所以不會錯過喚醒
+                    FUTEX_WAIT => match unsafe { read_user::<u32>(uaddr, current) } {
+                        Some(value) if value == a2 as u32 => {
+                            let queue = unsafe { (*queues).entry(key).or_insert_with(task::WaitQueue::new) };
+                            scheduler.block_current(queue);
+                            task::sched(scheduler);
+                            0
+                        }
+                        _ => (-1isize) as u64,
+                    },
+                    FUTEX_WAKE => unsafe {
+                        match (*queues).get_mut(&key) {
+                            Some(queue) => {
+                                let woken = scheduler.wake_some(queue, a2 as usize);
+                                if queue.is_empty() { (*queues).remove(&key); }
+                                woken as u64
+                            }
+                            None => 0,
+                        }
+                    },
+                    _ => (-1isize) as u64,
+                }
+            };
         },
 
         WAIT => {
-            let _pid = a0 as isize; 
-            let code_ptr_vaddr = a1 as usize; 
+            // waitpid(pid, status, options)：pid == -1 代表任意子行程 (不包含 Thread)。
+            // Thread 只能以 tid 指定，而且同一個行程 (共用位址空間) 的任何 Task 都可以 join 它
+            let pid = a0 as isize;
+            let code_ptr_vaddr = a1 as usize;
+            let options = a2;
+            let parent_id = scheduler.current_task().id;
+            let root_ppn = scheduler.current_task().root_ppn;
 
-            let mut zombie_idx = None;
-            let mut has_children = false;
+            ctx.regs[10] = loop {
+                let mut zombie_pid = None;
+                let mut has_children = false;
 
-            for (i, t) in scheduler.tasks.iter().enumerate() {
-                if t.id > 1 { // 忽略 Shell 和 Idle
-                    has_children = true;
-                    if t.state == TaskState::Zombie {
-                        zombie_idx = Some(i);
-                        break;
+                if pid == -1 {
+                    for t in scheduler.tasks.iter() {
+                        if t.parent_id != parent_id || t.id == parent_id || t.is_thread { continue; }
+                        has_children = true;
+                        if t.state == TaskState::Zombie {
+                            zombie_pid = Some(t.id);
+                            break;
+                        }
                     }
-                }
-            }
-
-            if let Some(idx) = zombie_idx {
-                let t = scheduler.tasks.remove(idx);
-                // 修正索引位移
-                if scheduler.current_index >= idx && scheduler.current_index > 0 {
-                    scheduler.current_index -= 1;
+                } else if let Some(t) = scheduler.find(pid as usize) && t.id != parent_id
+                    && (t.parent_id == parent_id || (t.is_thread && root_ppn != 0 && t.root_ppn == root_ppn)) {
+                    has_children = true;
+                    if t.state == TaskState::Zombie { zombie_pid = Some(t.id); }
                 }
 
-                if let Some(kptr) = unsafe { user_to_kernel_ptr::<i32>(code_ptr_vaddr, scheduler.current_task()) } {
-                     unsafe { *kptr = t.exit_code; }
+                if let Some(t) = zombie_pid.and_then(|zpid| scheduler.remove(zpid)) {
+                    // 回收子行程的位址空間 (核心堆疊隨 Box<Task> 一起釋放)；
+                    // Thread 共用的位址空間要等最後一個使用者被回收才釋放
+                    if t.root_ppn != 0 && scheduler.address_space_users(t.root_ppn) == 0 {
+                        unsafe { free_user_page_table((t.root_ppn << 12) as *mut page_table::PageTable); }
+                    }
+                    // status 指標可以是 NULL (不需要結束狀態)，寫入失敗就忽略
+                    unsafe { write_user(code_ptr_vaddr, &t.exit_code, scheduler.current_task()); }
+                    break t.id as u64;
+                } else if !has_children && parent_id != task::INIT_PID {
+                    break (-1isize) as u64;
+                } else if options & WNOHANG != 0 {
+                    break 0;
                 }
-
-                ctx.regs[10] = t.id as u64;
-            } else if has_children {
-                ctx.regs[10] = (-1isize) as u64;
-            } else {
-                ctx.regs[10] = (-2isize) as u64;
-            }
+                // 子行程仍在執行 (init 則是等待未來的孤兒)：睡到子行程 EXIT 喚醒後再找一次
+                let waiters = &raw mut task::CHILD_EXIT;
+                scheduler.block_current(unsafe { &mut *waiters });
+                scheduler = task::sched(scheduler);
+            };
         },
 
         FILE_LEN => {
+            // 檔名可以是路徑 (例如動態載入器讀取 /lib 中的共用函式庫)
+            // 讀取目錄時這個 Task 可能在 DISK_WAITERS 上睡眠，之後要重新取得 current_task
             let current_task = scheduler.current_task();
-            if let Some(kptr) = unsafe { user_to_kernel_ptr::<u8>(a0 as usize, current_task) } {
-                let slice = unsafe { core::slice::from_raw_parts(kptr, a1 as usize) };
-                let fname = core::str::from_utf8(slice).unwrap_or("");
-                if let Some(data) = fs::get_file_content(fname) { ctx.regs[10] = data.len() as u64; }
-                else { ctx.regs[10] = (-1isize) as u64; }
-            } else { ctx.regs[10] = (-1isize) as u64; }
+            let cwd = current_task.cwd;
+            let data = unsafe { user_str(a0 as usize, a1 as usize, current_task) }
+                .and_then(|fname| fs::get_file_by_path(&mut scheduler, cwd, &fname));
+            ctx.regs[10] = match data {
+                Some(data) => data.len() as u64,
+                None => (-1isize) as u64,
+            };
         },
         FILE_READ => {
             let current_task = scheduler.current_task();
-            let ptrs = unsafe { (user_to_kernel_ptr::<u8>(a0 as usize, current_task), user_to_kernel_ptr::<u8>(a2 as usize, current_task)) };
-            if let (Some(kname), Some(kbuf)) = ptrs {
-                unsafe {
-                    let fname = core::str::from_utf8(core::slice::from_raw_parts(kname, a1 as usize)).unwrap_or("");
-                    let user_buf = core::slice::from_raw_parts_mut(kbuf, a3 as usize);
-                    if let Some(data) = fs::get_file_content(fname) {
-                        let len = core::cmp::min(data.len(), user_buf.len());
-                        user_buf[..len].copy_from_slice(&data[..len]);
-                        ctx.regs[10] = len as u64;
-                    } else { ctx.regs[10] = (-1isize) as u64; }
+            let cwd = current_task.cwd;
+            let data = unsafe { user_str(a0 as usize, a1 as usize, current_task) }
+                .and_then(|fname| fs::get_file_by_path(&mut scheduler, cwd, &fname));
+            ctx.regs[10] = match data {
+                Some(data) => {
+                    let len = core::cmp::min(data.len(), a3 as usize);
+                    if unsafe { copy_to_user(a2 as usize, &data[..len], scheduler.current_task()) } { len as u64 } else { (-1isize) as u64 }
                 }
-            } else { ctx.regs[10] = (-1isize) as u64; }
+                None => (-1isize) as u64,
+            };
         },
         FILE_WRITE => {
             let current_task = scheduler.current_task();
-            let ptrs = unsafe { (user_to_kernel_ptr::<u8>(a0 as usize, current_task), user_to_kernel_ptr::<u8>(a2 as usize, current_task)) };
-            if let (Some(kname), Some(kdata)) = ptrs {
-                unsafe {
-                    let fname = core::str::from_utf8(core::slice::from_raw_parts(kname, a1 as usize)).unwrap_or("");
-                    let data = core::slice::from_raw_parts(kdata, a3 as usize);
-                    ctx.regs[10] = fs::write_file(fname, data) as u64;
-                }
+            let args = unsafe { (user_str(a0 as usize, a1 as usize, current_task), copy_from_user(a2 as usize, a3 as usize, current_task)) };
+            if let (Some(fname), Some(data)) = args {
+                ctx.regs[10] = fs::write_file(current_task.cwd, &fname, &data) as u64;
             } else { ctx.regs[10] = (-1isize) as u64; }
         },
         CHDIR => {
             let current_task = scheduler.current_task();
-            if let Some(kptr) = unsafe { user_to_kernel_ptr::<u8>(a0 as usize, current_task) } {
-                unsafe {
-                    let fname = core::str::from_utf8(core::slice::from_raw_parts(kptr, a1 as usize)).unwrap_or("");
-                    ctx.regs[10] = fs::change_dir(fname) as u64;
-                }
+            let mut cwd = current_task.cwd;
+            if let Some(fname) = unsafe { user_str(a0 as usize, a1 as usize, current_task) } {
+                ctx.regs[10] = fs::change_dir(&mut scheduler, &mut cwd, &fname) as u64;
+                scheduler.current_task().cwd = cwd;
             } else { ctx.regs[10] = (-1isize) as u64; }
         },
         FILE_LIST => {
+            let cwd = scheduler.current_task().cwd;
+            let files = fs::list_files(&mut scheduler, cwd);
             let current_task = scheduler.current_task();
-            if let Some(kptr) = unsafe { user_to_kernel_ptr::<u8>(a1 as usize, current_task) } {
-                unsafe {
-                    let user_buf = core::slice::from_raw_parts_mut(kptr, a2 as usize);
-                    let files = fs::list_files();
-                    if (a0 as usize) < files.len() {
-                        let (ftype, name) = &files[a0 as usize];
-                        let display_name = if *ftype == 1 { alloc::format!("{}/", name) } else { alloc::format!("{}", name) };
-                        let bytes = display_name.as_bytes();
-                        let len = core::cmp::min(bytes.len(), user_buf.len());
-                        user_buf[..len].copy_from_slice(&bytes[..len]);
-                        ctx.regs[10] = len as u64;
-                    } else { ctx.regs[10] = (-1isize) as u64; }
-                }
+            if (a0 as usize) < files.len() {
+                let (ftype, name) = &files[a0 as usize];
+                let display_name = if *ftype == 1 { alloc::format!("{}/", name) } else { alloc::format!("{}", name) };
+                let bytes = display_name.as_bytes();
+                let len = core::cmp::min(bytes.len(), a2 as usize);
+                ctx.regs[10] = if unsafe { copy_to_user(a1 as usize, &bytes[..len], current_task) } { len as u64 } else { (-1isize) as u64 };
             } else { ctx.regs[10] = (-1isize) as u64; }
         },
-        EXEC => {
+        SPAWN => {
+            // spawn(path, argv, envp)：以 path 建立新的子行程，回傳它的 PID；失敗時回傳 ExecError 的錯誤碼
             let current_task = scheduler.current_task();
-            let ptrs = unsafe { (user_to_kernel_ptr::<u8>(a0 as usize, current_task), user_to_kernel_ptr::<&str>(a2 as usize, current_task)) };
-            if let (Some(kelf), Some(kargv)) = ptrs {
-                unsafe {
-                    let elf_data = core::slice::from_raw_parts(kelf, a1 as usize);
-                    let argv_ptr = kargv; 
-                    let argc = a3 as usize;
-                    let argv_slice = core::slice::from_raw_parts(argv_ptr, argc);
-                    
-                    let new_table = new_user_page_table();
-                    if new_table.is_null() { ctx.regs[10] = (-1isize) as u64; }
-                    else {
-                        if let Some(entry) = elf::load_elf(elf_data, &mut *new_table) {
-                            let stack_frame = frame::alloc_frame();
-                            let stack_vaddr = 0xF000_0000;
-                            page_table::map(&mut *new_table, stack_vaddr, stack_frame, PTE_U | PTE_R | PTE_W);
-                            let stack_top_paddr = stack_frame + 4096;
-                            let mut sp_paddr = stack_top_paddr;
-                            let mut str_vaddrs = Vec::new();
-                            for arg in argv_slice {
-                                let bytes = arg.as_bytes();
-                                let len = bytes.len() + 1; 
-                                sp_paddr -= len;
-                                let dest = sp_paddr as *mut u8;
-                                core::ptr::copy_nonoverlapping(bytes.as_ptr(), dest, bytes.len());
-                                *dest.add(bytes.len()) = 0; 
-                                str_vaddrs.push(stack_vaddr + (sp_paddr - stack_frame));
-                            }
-                            sp_paddr -= sp_paddr % 8;
-                            sp_paddr -= (str_vaddrs.len() + 1) * 8; 
-                            let argv_vaddr = stack_vaddr + (sp_paddr - stack_frame);
-                            let ptr_array = sp_paddr as *mut usize;
-                            for (i, vaddr) in str_vaddrs.iter().enumerate() { *ptr_array.add(i) = *vaddr; }
-                            *ptr_array.add(str_vaddrs.len()) = 0; 
-                            let sp_vaddr = stack_vaddr + (sp_paddr - stack_frame);
-                            let new_pid = scheduler.tasks.len();
-                            let mut new_task = Task::new_user(new_pid);
-                            new_task.root_ppn = (new_table as usize) >> 12;
-                            new_task.context.mepc = entry;
-                            new_task.context.regs[2] = sp_vaddr as u64;
-                            new_task.context.regs[10] = argc as u64;
-                            new_task.context.regs[11] = argv_vaddr as u64;
-                            scheduler.spawn(new_task);
-                            ctx.regs[10] = new_pid as u64;
-                        } else { ctx.regs[10] = (-1isize) as u64; }
-                    }
+            let parent_cwd = current_task.cwd;
+            let parent_id = current_task.id;
+            let image = unsafe { user_exec_args(a0 as usize, a1 as usize, a2 as usize, current_task) }
+                .and_then(|(path, argv, envp)| unsafe { exec::load_program(&mut scheduler, parent_cwd, &path, &argv, &envp) });
+            ctx.regs[10] = match image {
+                Ok(image) => {
+                    let new_pid = scheduler.alloc_pid();
+                    let mut new_task = Task::new_user(new_pid);
+                    new_task.root_ppn = (image.root as usize) >> 12;
+                    new_task.user_stack = image.stack;
+                    new_task.vm = new_vm_table(image.heap_start);
+                    new_task.cwd = parent_cwd;
+                    new_task.parent_id = parent_id;
+                    new_task.context.sepc = image.entry;
+                    new_task.context.regs[2] = image.sp as u64;
+                    scheduler.spawn(new_task);
+                    new_pid as u64
                 }
-            } else { ctx.regs[10] = (-1isize) as u64; }
+                Err(e) => e.code() as u64,
+            };
+        },
+        EXECVE => {
+            // execve(path, argv, envp)：以 path 的映像檔取代目前的位址空間，PID、父行程與檔案表不變。
+            // 成功時不會回到呼叫者，而是從新程式的 entry 開始執行 (堆疊的配置見 exec::load_program)
+            let current_task = scheduler.current_task();
+            let old_root = current_task.root_ppn;
+            let cwd = current_task.cwd;
+            let args = unsafe { user_exec_args(a0 as usize, a1 as usize, a2 as usize, current_task) };
+            // 其他 Thread 還在使用這個位址空間時不能把它換掉
+            // (已經結束、尚未回收的 Thread 不算，成功後會在下面一起回收)
+            let image = if old_root != 0 && scheduler.live_address_space_users(old_root) > 1 { Err(ExecError::Busy) } else {
+                args.and_then(|(path, argv, envp)| unsafe { exec::load_program(&mut scheduler, cwd, &path, &argv, &envp) })
+            };
+            // 讀取執行檔時這個 Task 可能睡眠過，期間它的其他 Thread 可能又建立了新的 Thread
+            let image = image.and_then(|image| {
+                if old_root != 0 && scheduler.live_address_space_users(old_root) > 1 {
+                    unsafe { free_user_page_table(image.root); }
+                    Err(ExecError::Busy)
+                } else { Ok(image) }
+            });
+            let image = match image {
+                Ok(image) => image,
+                Err(e) => {
+                    ctx.regs[10] = e.code() as u64;
+                    ctx.sepc += 4;
+                    return;
+                }
+            };
+
+            let current_task = scheduler.current_task();
+            current_task.root_ppn = (image.root as usize) >> 12;
+            current_task.user_stack = image.stack;
+            current_task.vm = new_vm_table(image.heap_start);
+            // 核心 Task (例如 shell) 也能 execve，之後就不再需要核心 Heap 中的堆疊
+            current_task.stack = Vec::new();
+
+            // 舊映像檔的暫存器 (包含 FP) 全部清除，只保留核心堆疊與 hart 編號
+            let (kernel_sp, hartid) = (ctx.kernel_sp, ctx.hartid);
+            *ctx = task::Context::empty();
+            ctx.kernel_sp = kernel_sp;
+            ctx.hartid = hartid;
+            ctx.satp = current_task.satp();
+            ctx.sepc = image.entry;
+            ctx.regs[2] = image.sp as u64;
+            fpu::restore(ctx);
+
+            // 返回使用者模式時會切換到新的 satp，舊的 Page Table 已經沒有人使用。
+            // 舊映像檔中已經結束的 Thread 不會再有人 WAIT (新程式不知道它們)，一起回收
+            if old_root != 0 {
+                let zombies: Vec<usize> = scheduler.tasks.iter()
+                    .filter(|t| t.root_ppn == old_root && t.state == TaskState::Zombie).map(|t| t.id).collect();
+                for pid in zombies { scheduler.remove(pid); }
+                unsafe { free_user_page_table((old_root << 12) as *mut page_table::PageTable); }
+            }
+            // sepc 已經是新程式的 entry，不能再推進
+            return;
+        },
+        BRK => {
+            // brk(addr)：把 Heap 的結尾移到 addr，回傳新的結尾；失敗或 addr 為 0 時回傳目前的結尾 (sbrk 由 ulib 以它實作)
+            let current_task = scheduler.current_task();
+            ctx.regs[10] = if current_task.root_ppn == 0 { 0 } else {
+                let root = unsafe { &mut *((current_task.root_ppn << 12) as *mut page_table::PageTable) };
+                (unsafe { current_task.vm.lock().set_brk(root, a0 as usize, current_task.shares_address_space()) }) as u64
+            };
+        },
+        MMAP => {
+            // mmap(addr, len, prot, flags, fd, offset)：只支援匿名的私有映射 (addr 只是提示，一律由核心選擇位址)
+            // 成功回傳起始位址，失敗回傳 -1
+            let current_task = scheduler.current_task();
+            let flags = a3 as usize;
+            let anonymous = flags & (vm::MAP_PRIVATE | vm::MAP_ANONYMOUS) == vm::MAP_PRIVATE | vm::MAP_ANONYMOUS;
+            let addr = if anonymous && current_task.root_ppn != 0 {
+                let root = unsafe { &mut *((current_task.root_ppn << 12) as *mut page_table::PageTable) };
+                unsafe { current_task.vm.lock().mmap(root, &current_task.user_stack, a1 as usize, a2 as usize) }
+            } else { None };
+            ctx.regs[10] = addr.map_or((-1isize) as u64, |addr| addr as u64);
+        },
+        MUNMAP => {
+            // munmap(addr, len)：只能拆掉 MMAP 配置的區段 (或其中的一部分)，成功回傳 0
+            let current_task = scheduler.current_task();
+            let ok = current_task.root_ppn != 0 && {
+                let root = unsafe { &mut *((current_task.root_ppn << 12) as *mut page_table::PageTable) };
+                unsafe { current_task.vm.lock().munmap(root, a0 as usize, a1 as usize, current_task.shares_address_space()) }
+            };
+            ctx.regs[10] = if ok { 0 } else { (-1isize) as u64 };
+        },
+        MPROTECT => {
+            // mprotect(addr, len, prot)：同樣遵守 W^X，成功回傳 0
+            let current_task = scheduler.current_task();
+            let ok = current_task.root_ppn != 0 && {
+                let root = unsafe { &mut *((current_task.root_ppn << 12) as *mut page_table::PageTable) };
+                unsafe { vm::protect(root, a0 as usize, a1 as usize, a2 as usize, current_task.shares_address_space()) }
+            };
+            ctx.regs[10] = if ok { 0 } else { (-1isize) as u64 };
         },
         DISK_READ => {
             let sector = a0;
             let current_task = scheduler.current_task();
-            if let Some(kbuf) = unsafe { user_to_kernel_ptr::<u8>(a1 as usize, current_task) } {
-                let data = crate::virtio::read_disk(sector);
-                unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), kbuf, 512); }
+            // 先確認緩衝區可寫，才不會送出讀取後卻無處可放
+            if unsafe { user_chunks(a1 as usize, 512, true, current_task) }.is_none() {
+                ctx.regs[10] = (-1isize) as u64;
+            } else {
+                // 請求已送出 (或裝置忙碌)：睡到完成中斷為止，醒來後在這裡繼續取回資料
+                let data = virtio::read_sector(&mut scheduler, sector);
+                // 成功回傳 0；睡眠期間緩衝區的映射可能已經改變，寫入失敗就回傳 -1
+                ctx.regs[10] = if unsafe { copy_to_user(a1 as usize, &data, scheduler.current_task()) } { 0 } else { (-1isize) as u64 };
             }
         },
         _ => println!("Unknown Syscall: {}", id),
     }
     
-    // 只有非排程相關的 Syscall 才會執行到這裡
-    ctx.mepc += 4;
-    ctx
+    ctx.sepc += 4;
 }
\ No newline at end of file
@@ -1,30 +1,146 @@
 // === FILE: ./eos1/src/task.rs ===
 use alloc::vec::Vec;
 use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use alloc::vec;
-use crate::mm::page_table::KERNEL_PAGE_TABLE;
+use crate::mm::page_table::KERNEL_SATP;
+use crate::mm::vm::{UserStack, VmSpace};
+use crate::fs::ROOT_DIR_SECTOR;
+use crate::timer;
+use crate::fpu;
+use crate::sync::{SpinLock, SpinLockGuard};
 
 pub const STACK_SIZE: usize = 16384;
+// 每個 Task 自己的核心堆疊：Trap Handler (包含 Syscall) 在上面執行，可以在中途睡眠
+pub const KERNEL_STACK_SIZE: usize = 16384;
+
+// init 負責回收父行程已結束的孤兒行程
+pub const INIT_PID: usize = 1;
+
+// --- MLFQ (多層回饋佇列) ---
+// Level 0 優先權最高。用完整個時間片會被降一級，因 I/O 阻塞則回到自己的基本優先權
+pub const MLFQ_LEVELS: usize = 3;
+// 各層的時間片長度 (mtime ticks，QEMU virt 為 10MHz)
+pub const QUANTUM: [u64; MLFQ_LEVELS] = [200_000, 500_000, 1_000_000];
+// 每經過這麼多次時間片用完，就把所有 Task 拉回基本優先權，避免低層 Task 餓死
+const BOOST_PERIOD: usize = 50;
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum TaskState {
     Running, 
+    Blocked, // 在某個 WaitQueue 上睡眠，排程器不會選它
     Zombie,  
 }
 
+/// 等待佇列：記錄因同一個事件而阻塞的 Task PID
+pub struct WaitQueue {
+    waiters: Vec<usize>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { waiters: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+}
+
+// 等待子行程結束的父行程 (WAIT)。
+// 所有 WaitQueue 都只在持有 SCHEDULER 的鎖時存取，因此不需要自己的鎖
+pub static mut CHILD_EXIT: WaitQueue = WaitQueue::new();
+
+// 在 futex 上等待的 Task：以 (位址空間的 root_ppn, 使用者位址) 區分，佇列空了就移除
+pub static mut FUTEX_WAITERS: BTreeMap<(usize, usize), WaitQueue> = BTreeMap::new();
+
+// --- WAIT 回傳的 status (與 POSIX 的 wait status 相同的編碼) ---
+// 正常結束：(exit code & 0xff) << 8；被訊號終止：訊號編號放在低 7 位元
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGBUS: i32 = 7;
+pub const SIGSEGV: i32 = 11;
+
+pub fn exit_status(code: i32) -> i32 { (code & 0xff) << 8 }
+
+// 使用者行程的 sstatus：SPP = U-Mode、SPIE = 1 (回到 Task 後開中斷)、FS = Initial
+pub const USER_SSTATUS: u64 = (1 << 5) | (1 << 13);
+// 核心 Task (Shell、init、Idle) 在 S-Mode 執行：只使用核心映像檔中沒有 PTE_U 的頁面，Idle 也才能執行 wfi
+pub const KERNEL_SSTATUS: u64 = (1 << 8) | (1 << 5) | (1 << 13);
+
+// 目前支援的 hart 數量上限 (entry.S、trap.S 與 Firmware 的每個 hart 資料都以此配置)
+pub const MAX_HARTS: usize = 8;
+
 #[repr(C, align(16))]
 #[derive(Copy, Clone)]
 pub struct Context {
     pub regs: [u64; 32], 
-    pub mepc: u64,       
+    pub sepc: u64,       
+    pub sstatus: u64,
+    pub satp: u64, // 回到這個 Task 時使用的 Page Table (由 schedule 設定)
+    // 以下兩個欄位也由 pick_next 設定：trap.S 進入核心時切換到這個 Task 自己的核心堆疊，
+    // 並把 tp 設成 Task 目前所在的 hart
+    pub kernel_sp: u64,
+    pub hartid: u64,
+    // 浮點暫存器：進入核心時只有 sstatus.FS 為 Dirty 才保存，換 Task 時才載入 (見 fpu.rs)
+    pub fregs: [u64; 32],
+    pub fcsr: u64,
+}
+
+// trap.S 的 CONTEXT_SIZE (核心本身發生例外時在堆疊上放的 Context)
+const _: () = assert!(core::mem::size_of::<Context>() == 70 * 8);
+
+/// switch_to 保存的暫存器 (順序與 switch.S 相同)：只有 callee-saved 的部分，
+/// 其餘暫存器在呼叫 switch_to 的 Rust 程式碼中已經由編譯器保存
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SwitchContext {
+    pub ra: u64,
+    pub sp: u64,
+    pub s: [u64; 12],
+}
+
+impl SwitchContext {
+    pub const fn empty() -> Self {
+        Self { ra: 0, sp: 0, s: [0; 12] }
+    }
 }
 
+unsafe extern "C" {
+    fn switch_to(old: *mut SwitchContext, new: *const SwitchContext);
+    fn trap_return(ctx: *mut Context) -> !;
+}
+
+// 各 hart 的排程迴圈 (run_scheduler) 切換到 Task 時保存的暫存器，只有該 hart 自己會存取
+static mut HART_CONTEXTS: [SwitchContext; MAX_HARTS] = [SwitchContext::empty(); MAX_HARTS];
+
 impl Context {
     pub const fn empty() -> Self {
-        Self { regs: [0; 32], mepc: 0 }
+        Self { regs: [0; 32], sepc: 0, sstatus: USER_SSTATUS, satp: 0, kernel_sp: 0, hartid: 0, fregs: [0; 32], fcsr: 0 }
     }
 }
 
+// 讀取 CpuStat 的結果 (單位：mtime ticks)
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CpuStat {
+    pub uptime: u64,
+    pub idle: u64,
+}
+
+// S-Mode 無法讀取 mhartid：由 Firmware 進入核心時透過 a0 傳入，之後放在 tp。
+// Task 的 tp 會在 trap 時被保存，trap.S 再從 Context.hartid 重新載入
+pub fn set_hart_id(id: usize) {
+    unsafe { core::arch::asm!("mv tp, {}", in(reg) id); }
+}
+
+pub fn hart_id() -> usize {
+    let id: usize;
+    unsafe { core::arch::asm!("mv {}, tp", out(reg) id); }
+    id
+}
+
 // 檔案描述符
 #[derive(Clone)]
 pub enum FileDescriptor {
@@ -32,15 +148,38 @@ pub enum FileDescriptor {
     Stdout,
 }
 
+// 檔案表：同一個行程的 Thread 共用，fork 時複製一份
+pub type FileTable = Arc<SpinLock<Vec<Option<FileDescriptor>>>>;
+
+pub fn new_file_table() -> FileTable {
+    Arc::new(SpinLock::new(vec![Some(FileDescriptor::Stdin), Some(FileDescriptor::Stdout)]))
+}
+
+// 行程的 Heap 與 MMAP 區段，和 Page Table 一樣由同一個行程的 Thread 共用
+pub type VmTable = Arc<SpinLock<VmSpace>>;
+
+pub fn new_vm_table(heap_start: usize) -> VmTable {
+    Arc::new(SpinLock::new(VmSpace::new(heap_start)))
+}
+
 #[repr(C, align(16))]
 pub struct Task {
     pub id: usize,
-    pub stack: Vec<u8>, 
-    pub context: Context,
-    pub root_ppn: usize,
-    pub files: Vec<Option<FileDescriptor>>,
+    pub parent_id: usize,
+    pub stack: Vec<u8>, // 核心 Task 執行時使用的堆疊 (使用者行程的堆疊在自己的位址空間裡)
+    pub kstack: Vec<u8>,
+    pub context: Context, // 進入核心時保存的使用者暫存器 (Trap Frame)
+    pub switch_context: SwitchContext, // 在核心裡讓出 CPU 時保存的暫存器
+    pub root_ppn: usize, // 同一個行程的 Thread 共用 (見 address_space_users)
+    pub user_stack: UserStack, // 主程式的堆疊範圍，Page Fault 時依需要成長
+    pub vm: VmTable,
+    pub files: FileTable,
+    pub cwd: u32, // 工作目錄所在的 Sector
+    pub priority: usize, // 基本優先權 (setpriority 設定)，也是 level 的上限
+    pub level: usize,    // 目前所在的 MLFQ 佇列
     pub state: TaskState,
-    pub exit_code: i32,
+    pub exit_code: i32, // WAIT 回傳的 status (見 exit_status)
+    pub is_thread: bool, // THREAD_CREATE 建立的 Task：不是子行程，只能以 tid 明確 WAIT (join)
 }
 
 impl Task {
@@ -51,93 +190,428 @@ impl Task {
 
         let mut task = Self {
             id,
+            parent_id: INIT_PID,
             stack,
+            kstack: vec![0u8; KERNEL_STACK_SIZE],
             context: Context::empty(),
+            switch_context: SwitchContext::empty(),
             root_ppn: 0,
-            files: vec![Some(FileDescriptor::Stdin), Some(FileDescriptor::Stdout)],
+            user_stack: UserStack::default(),
+            vm: new_vm_table(0),
+            files: new_file_table(),
+            cwd: ROOT_DIR_SECTOR,
+            priority: 0,
+            level: 0,
             state: TaskState::Running, 
             exit_code: 0,
+            is_thread: false,
         };
         
         task.context.regs[2] = aligned_sp as u64;
-        task.context.mepc = entry as u64;
+        task.context.sepc = entry as u64;
+        task.context.sstatus = KERNEL_SSTATUS;
+        task.init_switch_context();
+        task
+    }
+
+    /// 沒有其他 Task 可執行時才會被選中，以 wfi 等待中斷
+    pub fn new_idle(id: usize) -> Self {
+        let mut task = Self::new_kernel(id, idle_entry);
+        task.priority = MLFQ_LEVELS - 1;
+        task.level = MLFQ_LEVELS - 1;
         task
     }
 
+    /// 是否還有其他 Thread 共用這個位址空間：它們可能正在其他 hart 上執行，
+    /// 降級或取消 PTE 時必須連同其他 hart 的 TLB 一起清除 (見 page_table::flush_tlb)
+    pub fn shares_address_space(&self) -> bool {
+        Arc::strong_count(&self.vm) > 1
+    }
+
+    /// 這個 Task 的 satp：核心 Task 使用核心的 Page Table
+    pub fn satp(&self) -> u64 {
+        if self.root_ppn != 0 { (8 << 60) | self.root_ppn as u64 } else { unsafe { KERNEL_SATP as u64 } }
+    }
+
     pub fn new_user(id: usize) -> Self {
-        let stack = vec![0u8; STACK_SIZE];
-        Self {
+        let mut task = Self {
             id,
-            stack,
+            parent_id: INIT_PID,
+            stack: Vec::new(),
+            kstack: vec![0u8; KERNEL_STACK_SIZE],
             context: Context::empty(),
+            switch_context: SwitchContext::empty(),
             root_ppn: 0,
-            files: vec![Some(FileDescriptor::Stdin), Some(FileDescriptor::Stdout)],
+            user_stack: UserStack::default(),
+            vm: new_vm_table(0),
+            files: new_file_table(),
+            cwd: ROOT_DIR_SECTOR,
+            priority: 0,
+            level: 0,
             state: TaskState::Running,
             exit_code: 0,
-        }
+            is_thread: false,
+        };
+        task.init_switch_context();
+        task
+    }
+
+    pub fn kernel_stack_top(&self) -> usize {
+        (self.kstack.as_ptr() as usize + KERNEL_STACK_SIZE) & !0xF
+    }
+
+    /// 第一次被選中時，switch_to 會在這個 Task 的核心堆疊上「返回」到 task_start
+    fn init_switch_context(&mut self) {
+        self.switch_context.ra = task_start as *const () as u64;
+        self.switch_context.sp = self.kernel_stack_top() as u64;
     }
 }
 
 pub struct Scheduler {
     pub tasks: Vec<Box<Task>>,
-    pub current_index: usize,
+    next_pid: usize,
+    pid_index: BTreeMap<usize, usize>, // PID -> tasks 中的索引
+    expired_count: usize, // 距離上次全體 boost 後，時間片用完的次數
+    current: [Option<usize>; MAX_HARTS],   // 各 hart 正在執行的 Task PID
+    idle_pids: [Option<usize>; MAX_HARTS], // 各 hart 自己的 Idle Task，也代表該 hart 已經啟動
+    last_switch: [u64; MAX_HARTS], // 各 hart 上次切換 Task 的時間
+    idle_ticks: [u64; MAX_HARTS],  // 各 hart 累計執行 Idle Task 的時間
 }
 
-pub static mut SCHEDULER: Option<Scheduler> = None;
+// 所有 hart 共用一個執行佇列：Trap Handler 從進入到回到 Task 前都持有這把鎖，
+// 切換 Task 時鎖也不放開，而是交給切換過去的一方 (見 sched / run_scheduler)
+pub static SCHEDULER: SpinLock<Scheduler> = SpinLock::new(Scheduler::new());
+
+pub type SchedulerGuard = SpinLockGuard<'static, Scheduler>;
 
 impl Scheduler {
-    pub fn new() -> Self {
-        Self { tasks: Vec::new(), current_index: 0 }
+    pub const fn new() -> Self {
+        Self {
+            tasks: Vec::new(),
+            next_pid: 0,
+            pid_index: BTreeMap::new(),
+            expired_count: 0,
+            current: [None; MAX_HARTS],
+            idle_pids: [None; MAX_HARTS],
+            last_switch: [0; MAX_HARTS],
+            idle_ticks: [0; MAX_HARTS],
+        }
+    }
+
+    /// 配發新的 PID：單調遞增，已回收的 PID 不會被重複使用
+    pub fn alloc_pid(&mut self) -> usize {
+        let pid = self.next_pid;
+        self.next_pid += 1;
+        pid
+    }
+
+    pub fn spawn(&mut self, t: Task) {
+        self.pid_index.insert(t.id, self.tasks.len());
+        self.tasks.push(Box::new(t));
+    }
+
+    /// 登記 hart 的 Idle Task (每個 hart 啟動時建立一個)
+    pub fn spawn_idle(&mut self, t: Task, hart: usize) {
+        self.idle_pids[hart] = Some(t.id);
+        self.spawn(t);
+    }
+
+    fn is_idle(&self, index: usize) -> bool {
+        self.idle_pids.contains(&Some(self.tasks[index].id))
+    }
+
+    /// Task 是否正在其他 hart 上執行 (同一個 Context 不能同時在兩個 hart 上跑)
+    fn running_elsewhere(&self, pid: usize, hart: usize) -> bool {
+        self.current.iter().enumerate().any(|(h, &p)| h != hart && p == Some(pid))
+    }
+
+    fn current_is_idle(&self, hart: usize) -> bool {
+        self.current[hart].is_some() && self.current[hart] == self.idle_pids[hart]
+    }
+
+    /// 目前 hart 正在執行的 Task 在 tasks 中的索引
+    fn current_index(&self) -> Option<usize> {
+        self.current[hart_id()].and_then(|pid| self.pid_index.get(&pid).copied())
     }
 
-    pub fn init() { unsafe { SCHEDULER = Some(Self::new()); } }
+    /// 使用這個位址空間的 Task 數量 (包含尚未回收的 Zombie)
+    pub fn address_space_users(&self, root_ppn: usize) -> usize {
+        self.tasks.iter().filter(|t| t.root_ppn == root_ppn).count()
+    }
 
-    pub fn spawn(&mut self, t: Task) { self.tasks.push(Box::new(t)); }
+    /// 仍在執行 (不是 Zombie) 的使用者數量：EXECVE 只有在沒有其他存活的 Thread 時才能換掉位址空間
+    pub fn live_address_space_users(&self, root_ppn: usize) -> usize {
+        self.tasks.iter().filter(|t| t.root_ppn == root_ppn && t.state != TaskState::Zombie).count()
+    }
+
+    pub fn find(&mut self, pid: usize) -> Option<&mut Task> {
+        let idx = *self.pid_index.get(&pid)?;
+        Some(&mut self.tasks[idx])
+    }
 
-    pub unsafe fn schedule(&mut self) -> *mut Context {
+    /// 從排程器移除 Task (只會移除 Zombie，不會是任何 hart 正在執行的 Task)，並修正其後 Task 的索引
+    pub fn remove(&mut self, pid: usize) -> Option<Box<Task>> {
+        let idx = self.pid_index.remove(&pid)?;
+        let t = self.tasks.remove(idx);
+        for i in self.pid_index.values_mut() {
+            if *i > idx { *i -= 1; }
+        }
+        Some(t)
+    }
+
+    /// 為目前的 hart 選出 level 最小 (優先權最高) 的可執行 Task；同一層內從目前位置往後輪流 (Round Robin)。
+    /// 正在其他 hart 上執行的 Task 不會被選中；沒有任何 Task 可執行時才選這個 hart 的 Idle Task。
+    /// 回傳要切換過去的 SwitchContext
+    fn pick_next(&mut self) -> *const SwitchContext {
         if self.tasks.is_empty() { panic!("No tasks!"); }
 
-        let start_index = self.current_index;
-        loop {
-            self.current_index = (self.current_index + 1) % self.tasks.len();
-            
-            if self.tasks[self.current_index].state == TaskState::Running {
-                break;
-            }
-            
-            if self.current_index == start_index {
-                if self.tasks[self.current_index].state == TaskState::Zombie {
-                    panic!("All tasks are zombies!");
-                }
-                break;
-            }
+        let hart = hart_id();
+        let len = self.tasks.len();
+        let start = self.current_index().unwrap_or(len - 1);
+        let mut best: Option<usize> = None;
+        for offset in 1..=len {
+            let i = (start + offset) % len;
+            let t = &self.tasks[i];
+            if t.state != TaskState::Running || self.is_idle(i) || self.running_elsewhere(t.id, hart) { continue; }
+            if best.is_none_or(|b| t.level < self.tasks[b].level) { best = Some(i); }
         }
-        
-        let next_task = &mut self.tasks[self.current_index];
 
-        let satp_val = if next_task.root_ppn != 0 {
-            (8 << 60) | next_task.root_ppn
-        } else {
-            let kernel_root = unsafe { KERNEL_PAGE_TABLE as usize };
-            (8 << 60) | (kernel_root >> 12)
+        let next_pid = match best.map(|i| self.tasks[i].id).or(self.idle_pids[hart]) {
+            Some(pid) => pid,
+            None => panic!("No runnable tasks on hart {}!", hart),
         };
+
+        // Idle 時間統計：離開 Idle Task 時把這段時間記上
+        let now = timer::now();
+        if self.current_is_idle(hart) {
+            self.idle_ticks[hart] += now - self.last_switch[hart];
+        }
+        self.last_switch[hart] = now;
+        let switched = self.current[hart] != Some(next_pid);
+        self.current[hart] = Some(next_pid);
         
-        // [修正] 加上 unsafe
-        unsafe {
-            core::arch::asm!("csrw satp, {}", "sfence.vma", in(reg) satp_val);
+        let next_task = self.find(next_pid).unwrap();
+
+        // 換成別的 Task 時才載入它的 FP 暫存器 (同一個 Task 繼續執行時暫存器本來就是它的)
+        if switched { fpu::restore(&mut next_task.context); }
+
+        // 實際的 satp 切換在 trap.S 回到 Task 前進行
+        next_task.context.satp = next_task.satp();
+        next_task.context.kernel_sp = next_task.kernel_stack_top() as u64;
+        next_task.context.hartid = hart as u64;
+
+        // 依照所在層級設定這次的時間片
+        timer::set_next(QUANTUM[next_task.level]);
+
+        &raw const next_task.switch_context
+    }
+
+    /// 時間片用完 (Timer 中斷)：目前的 Task 降一級，並定期把所有 Task 拉回基本優先權
+    pub fn expire_current(&mut self) {
+        if self.current_is_idle(hart_id()) { return; }
+        let current = self.current_task();
+        if current.level + 1 < MLFQ_LEVELS { current.level += 1; }
+
+        self.expired_count += 1;
+        if self.expired_count >= BOOST_PERIOD {
+            self.expired_count = 0;
+            for t in self.tasks.iter_mut() { t.level = t.priority; }
+        }
+    }
+
+    /// 是否有比目前 Task 優先權更高的 Task 可以執行 (例如剛被中斷喚醒的互動式 Task)
+    pub fn should_preempt(&mut self) -> bool {
+        let hart = hart_id();
+        let current_is_idle = self.current_is_idle(hart);
+        let current_level = self.current_task().level;
+        (0..self.tasks.len()).any(|i| {
+            let t = &self.tasks[i];
+            t.state == TaskState::Running && !self.is_idle(i) && !self.running_elsewhere(t.id, hart)
+                && (current_is_idle || t.level < current_level)
+        })
+    }
+
+    /// 讀取某個 hart 的開機時間與 Idle 時間 (包含目前正在進行的 Idle 區間)
+    pub fn cpu_stat(&self, hart: usize) -> Option<CpuStat> {
+        self.idle_pids.get(hart)?.as_ref()?;
+        let now = timer::now();
+        let mut idle = self.idle_ticks[hart];
+        if self.current_is_idle(hart) {
+            idle += now - self.last_switch[hart];
         }
+        Some(CpuStat { uptime: now, idle })
+    }
 
-        &mut next_task.context as *mut Context
+    /// 設定基本優先權，並讓 Task 立刻回到該層
+    pub fn set_priority(&mut self, pid: usize, priority: usize) -> bool {
+        if priority >= MLFQ_LEVELS { return false; }
+        match self.find(pid) {
+            Some(t) => { t.priority = priority; t.level = priority; true }
+            None => false,
+        }
+    }
+
+    /// pid 是否為 ancestor 本身或它的後代 (沿著 parent_id 往上找；孤兒已經交給 init)
+    pub fn is_descendant(&self, pid: usize, ancestor: usize) -> bool {
+        let mut cur = pid;
+        // parent_id 形成的鏈最長就是 Task 的數量，避免 init 等自己是自己父行程的 Task 造成無限迴圈
+        for _ in 0..=self.tasks.len() {
+            if cur == ancestor { return true; }
+            let Some(&idx) = self.pid_index.get(&cur) else { return false };
+            let parent = self.tasks[idx].parent_id;
+            if parent == cur { return false; }
+            cur = parent;
+        }
+        false
     }
 
     pub fn current_task(&mut self) -> &mut Task {
-        &mut self.tasks[self.current_index]
+        let idx = self.current_index().expect("no current task on this hart");
+        &mut self.tasks[idx]
+    }
+
+    /// 目前的 Task 結束 (EXIT 或被核心終止)：變成 Zombie 等待父行程 WAIT 回收
+    pub fn exit_current(&mut self, status: i32) {
+        let current = self.current_task();
+        current.state = TaskState::Zombie;
+        current.exit_code = status;
+        let exiting_id = current.id;
+        let parent_id = current.parent_id;
+        let root_ppn = current.root_ppn;
+        let is_thread = current.is_thread;
+
+        // 孤兒行程交給 init 收屍。Thread 不是子行程，仍然留給同一個行程的其他 Task 以 tid join
+        let mut has_orphans = false;
+        for t in self.tasks.iter_mut() {
+            if t.parent_id == exiting_id && !t.is_thread { t.parent_id = INIT_PID; has_orphans = true; }
+        }
+        // 行程的最後一個 Task 結束後沒有人能 join 剩下的 Thread 了：它們 (包含自己) 變成 init 的孤兒
+        if root_ppn != 0 && !self.tasks.iter().any(|t| t.root_ppn == root_ppn && t.state != TaskState::Zombie) {
+            for t in self.tasks.iter_mut() {
+                if t.root_ppn == root_ppn && t.is_thread {
+                    t.is_thread = false;
+                    t.parent_id = INIT_PID;
+                    has_orphans = true;
+                }
+            }
+        }
+        let waiters = &raw mut CHILD_EXIT;
+        let child_exit = unsafe { &mut *waiters };
+        self.wake(child_exit, parent_id);
+        // Thread 可能是被同一個行程的其他 Task join
+        if is_thread {
+            let joiners: Vec<usize> = self.tasks.iter().filter(|t| t.root_ppn == root_ppn).map(|t| t.id).collect();
+            for pid in joiners { self.wake(child_exit, pid); }
+        }
+        if has_orphans { self.wake(child_exit, INIT_PID); }
+    }
+
+    /// 讓目前的 Task 在 queue 上睡眠，呼叫者接著應該呼叫 sched()
+    pub fn block_current(&mut self, queue: &mut WaitQueue) {
+        let current = self.current_task();
+        current.state = TaskState::Blocked;
+        // 因等待 I/O 而讓出 CPU 的 Task 回到基本優先權
+        current.level = current.priority;
+        queue.waiters.push(current.id);
+    }
+
+    /// 讓目前的 Task 睡到 deadline (mtime)，呼叫者接著應該呼叫 sched()
+    pub fn sleep_current(&mut self, deadline: u64) {
+        let current = self.current_task();
+        current.state = TaskState::Blocked;
+        current.level = current.priority;
+        timer::add_sleeper(deadline, current.id);
+    }
+
+    /// 喚醒 deadline 已到的睡眠 Task
+    pub fn wake_sleepers(&mut self, now: u64) {
+        for pid in timer::take_expired(now) {
+            if let Some(t) = self.find(pid) && t.state == TaskState::Blocked {
+                t.state = TaskState::Running;
+            }
+        }
+    }
+
+    /// 喚醒 queue 上所有的 Task
+    pub fn wake_all(&mut self, queue: &mut WaitQueue) {
+        for pid in queue.waiters.drain(..) {
+            if let Some(t) = self.find(pid) && t.state == TaskState::Blocked {
+                t.state = TaskState::Running;
+            }
+        }
+    }
+
+    /// 依照睡眠的先後喚醒 queue 上最多 count 個 Task，回傳喚醒的數量
+    pub fn wake_some(&mut self, queue: &mut WaitQueue, count: usize) -> usize {
+        let count = count.min(queue.waiters.len());
+        let mut woken = 0;
+        for pid in queue.waiters.drain(..count) {
+            if let Some(t) = self.find(pid) && t.state == TaskState::Blocked {
+                t.state = TaskState::Running;
+                woken += 1;
+            }
+        }
+        woken
+    }
+
+    /// 只喚醒 queue 上指定的 Task，回傳它是否在 queue 上
+    pub fn wake(&mut self, queue: &mut WaitQueue, pid: usize) -> bool {
+        let Some(pos) = queue.waiters.iter().position(|&p| p == pid) else { return false };
+        queue.waiters.remove(pos);
+        if let Some(t) = self.find(pid) && t.state == TaskState::Blocked {
+            t.state = TaskState::Running;
+        }
+        true
+    }
+}
+
+/// 每個 hart 最後進入的排程迴圈 (在該 hart 的開機堆疊上執行)：
+/// 選出下一個 Task 切換過去，Task 呼叫 sched 讓出 CPU 時回到這裡。
+/// 鎖從 Task 那邊接手後一直持有到下一次 switch_to，由被選中的 Task 放開
+pub fn run_scheduler() -> ! {
+    let hart = hart_id();
+    let mut scheduler = SCHEDULER.lock();
+    loop {
+        let next = scheduler.pick_next();
+        core::mem::forget(scheduler);
+        unsafe {
+            switch_to(&raw mut HART_CONTEXTS[hart], next);
+            scheduler = SCHEDULER.inherit();
+        }
     }
 }
 
-pub fn get_scheduler() -> &'static mut Scheduler {
+/// 目前的 Task 讓出 CPU：呼叫前先設定好它的狀態 (仍是 Running 代表只是被搶占)。
+/// 切回這個 hart 的排程迴圈，直到某個 hart 再次選中它時才從這裡返回 (不一定是同一個 hart)，
+/// 返回時這個 Task 重新持有排程器的鎖
+pub fn sched(mut scheduler: SchedulerGuard) -> SchedulerGuard {
+    let hart = hart_id();
+    let task_context = &raw mut scheduler.current_task().switch_context;
+    core::mem::forget(scheduler);
     unsafe {
-        let ptr = &raw mut SCHEDULER;
-        (*ptr).as_mut().unwrap()
+        switch_to(task_context, &raw const HART_CONTEXTS[hart]);
+        SCHEDULER.inherit()
     }
-}
\ No newline at end of file
+}
+
+/// 與 sched 相同，但不取走呼叫者的 guard (給只拿到 &mut SchedulerGuard 的函式使用，例如 virtio::read_sector)。
+/// 返回時 guard 仍然持有鎖，但睡眠期間其他 Task 可能已經改變了排程器的內容
+pub fn sched_in_place(scheduler: &mut SchedulerGuard) {
+    // sched 不會 panic 後返回 (panic = abort)，guard 在讀出與寫回之間不會被 drop 兩次
+    unsafe { core::ptr::write(scheduler, sched(core::ptr::read(scheduler))); }
+}
+
+/// 新 Task 第一次被選中時從這裡開始：放開排程迴圈交過來的鎖，再經由 trap_return 進入 Task
+extern "C" fn task_start() -> ! {
+    let mut scheduler = unsafe { SCHEDULER.inherit() };
+    let ctx = &raw mut scheduler.current_task().context;
+    drop(scheduler);
+    unsafe { trap_return(ctx) }
+}
+
+/// Idle Task：在 S-Mode 開著中斷執行 wfi，直到 Timer 或 PLIC 中斷讓排程器換別的 Task
+pub extern "C" fn idle_entry() -> ! {
+    loop {
+        unsafe { core::arch::asm!("wfi"); }
+    }
+}
@@ -1,10 +1,53 @@
-const CLINT_MTIMECMP: *mut u64 = 0x0200_4000 as *mut u64;
-const CLINT_MTIME: *const u64 = 0x0200_BFF8 as *const u64;
-const INTERVAL: u64 = 1_000_000;
-
-pub fn set_next() {
-    unsafe {
-        let now = CLINT_MTIME.read_volatile();
-        CLINT_MTIMECMP.write_volatile(now + INTERVAL);
+use alloc::vec::Vec;
+use crate::sbi;
+use crate::sync::SpinLock;
+use crate::task::{hart_id, MAX_HARTS};
+
+// QEMU virt 的 time 以 10MHz 遞增
+pub const TICKS_PER_SEC: u64 = 10_000_000;
+
+// 各 hart 上目前 Task 時間片結束的時間點 (只有該 hart 自己會存取)
+static mut QUANTUM_END: [u64; MAX_HARTS] = [0; MAX_HARTS];
+
+// 睡眠中的 Task：(deadline, pid)，依照 deadline 由小到大排序，所有 hart 共用
+static SLEEPERS: SpinLock<Vec<(u64, usize)>> = SpinLock::new(Vec::new());
+
+pub fn now() -> u64 {
+    let time: u64;
+    unsafe { core::arch::asm!("rdtime {}", out(reg) time); }
+    time
+}
+
+/// interval 個 ticks 之後結束目前的時間片
+pub fn set_next(interval: u64) {
+    unsafe { QUANTUM_END[hart_id()] = now() + interval; }
+    rearm();
+}
+
+/// 時間片是否已經用完 (Timer 中斷也可能只是某個睡眠 Task 到期)
+pub fn quantum_expired(now: u64) -> bool {
+    now >= unsafe { QUANTUM_END[hart_id()] }
+}
+
+/// 這個 hart 的下一次 Timer 中斷 = min(時間片結束, 最早的睡眠 deadline)，由 Firmware 設定 mtimecmp
+pub fn rearm() {
+    let mut next = unsafe { QUANTUM_END[hart_id()] };
+    if let Some(&(deadline, _)) = SLEEPERS.lock().first() && deadline < next {
+        next = deadline;
     }
-}
\ No newline at end of file
+    sbi::set_timer(next);
+}
+
+/// 登記一個睡到 deadline 的 Task
+pub fn add_sleeper(deadline: u64, pid: usize) {
+    let mut sleepers = SLEEPERS.lock();
+    let pos = sleepers.iter().position(|&(d, _)| d > deadline).unwrap_or(sleepers.len());
+    sleepers.insert(pos, (deadline, pid));
+}
+
+/// 取出所有 deadline 已到的 Task PID
+pub fn take_expired(now: u64) -> Vec<usize> {
+    let mut sleepers = SLEEPERS.lock();
+    let count = sleepers.iter().take_while(|&&(d, _)| d <= now).count();
+    sleepers.drain(..count).map(|(_, pid)| pid).collect()
+}
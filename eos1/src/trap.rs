@@ -1,65 +1,146 @@
 // === FILE: ./eos1/src/trap.rs ===
 use crate::task::{self, Context};
 use crate::syscall;
-use crate::timer;
 use crate::plic;
-use crate::mm::page_table::KERNEL_PAGE_TABLE;
-use crate::shell;
+use crate::timer;
+use crate::fpu;
+use crate::mm::page_table::{self, PageTable};
+use crate::mm::vm;
 
-// 整合後的 Trap Handler
+// 整合後的 Trap Handler，在目前 Task 自己的核心堆疊上執行。
+// 需要換 Task 時呼叫 task::sched，等這個 Task 再被選中後才返回，所以一定回到同一個 Context
 #[unsafe(no_mangle)]
-pub extern "C" fn handle_trap(ctx_ptr: *mut Context) -> *mut Context {
-    let mcause: usize;
-    unsafe { core::arch::asm!("csrr {}, mcause", out(reg) mcause); }
+pub extern "C" fn handle_trap(ctx_ptr: *mut Context) {
+    let scause: usize;
+    unsafe { core::arch::asm!("csrr {}, scause", out(reg) scause); }
     
-    let is_interrupt = (mcause >> 63) != 0;
-    let code = mcause & 0xfff;
+    let is_interrupt = (scause >> 63) != 0;
+    let code = scause & 0xfff;
+
+    // Task 改過的 FP 暫存器先存起來，之後這個 hart 可能換去執行別的 Task
+    fpu::save(unsafe { &mut *ctx_ptr });
+
+    // 在 S-Mode (核心 Task、Idle Task 或核心本身) 發生例外：核心已經不可信，直接 panic。
+    // 必須在拿排程器的鎖之前檢查：例外可能發生在持有鎖的時候 (Syscall 或排程器裡)，SpinLock 不能重複取得
+    // (核心 Task 的 ecall 是 code 9，由 Firmware 轉回 S-Mode，屬於正常的 Syscall)
+    let sstatus = unsafe { (*ctx_ptr).sstatus };
+    if !is_interrupt && code != 8 && code != 9 && (sstatus >> 8) & 1 == 1 {
+        let stval: usize;
+        unsafe { core::arch::asm!("csrr {}, stval", out(reg) stval); }
+        let sepc = unsafe { (*ctx_ptr).sepc };
+        panic!("Kernel fault: {} at {:#x} (sepc={:#x}, hart={})", fault_name(code), stval, sepc, task::hart_id());
+    }
+
+    // 所有 hart 共用同一個執行佇列：整個 Trap 處理期間都持有排程器的鎖
+    let mut scheduler = task::SCHEDULER.lock();
 
     if is_interrupt {
         match code {
-            7 => { // Machine Timer Interrupt
-                timer::set_next();
-                let scheduler = task::get_scheduler();
-                return unsafe { scheduler.schedule() };
+            5 => { // Supervisor Timer Interrupt (Firmware 轉送)
+                let now = timer::now();
+                scheduler.wake_sleepers(now);
+
+                if timer::quantum_expired(now) {
+                    // 時間片用完：降級後重新排程 (pick_next 會設定下一次的時間片)
+                    scheduler.expire_current();
+                    task::sched(scheduler);
+                    return;
+                }
+
+                // 只是睡眠的 Task 到期：重新設定 Timer，被喚醒的 Task 優先權較高時切換過去
+                timer::rearm();
+                if scheduler.should_preempt() {
+                    task::sched(scheduler);
+                }
             }
-            11 => { // Machine External Interrupt (PLIC)
-                 plic::handle_interrupt();
-                 return ctx_ptr;
+            9 => { // Supervisor External Interrupt (PLIC)
+                 plic::handle_interrupt(&mut scheduler);
+                 // 被喚醒的 Task 優先權較高時立刻切換過去，互動式程式才不會卡在 CPU 密集的 Task 後面
+                 if scheduler.should_preempt() {
+                     task::sched(scheduler);
+                 }
             }
             _ => {
                 println!("[Kernel] Unexpected interrupt: {}", code);
-                return ctx_ptr;
             }
         }
     } else {
-        if code == 8 { 
-            return unsafe { syscall::dispatcher(&mut *ctx_ptr) };
+        // 8 = 使用者行程的 ecall；9 = 核心 Task 的 ecall (由 Firmware 轉回 S-Mode)
+        if code == 8 || code == 9 { 
+            unsafe { syscall::dispatcher(&mut *ctx_ptr, scheduler) };
+            return;
         }
         
-        let mtval: usize;
-        unsafe { core::arch::asm!("csrr {}, mtval", out(reg) mtval); }
-        println!("\n[Crash] mcause={}, mepc={:x}, mtval={:x}", code, unsafe { (*ctx_ptr).mepc }, mtval);
-        println!("User App crashed. Rebooting shell...");
-        
-        unsafe {
-            let kernel_root = KERNEL_PAGE_TABLE as usize;
-            core::arch::asm!("csrw satp, {}", "sfence.vma", in(reg) (8 << 60) | (kernel_root >> 12));
-            
-            let scheduler = task::get_scheduler();
-            if scheduler.tasks.len() > 2 { scheduler.tasks.truncate(2); }
-            scheduler.current_index = 0;
-            let shell_task = &mut scheduler.tasks[0];
-            
-            shell_task.root_ppn = 0;
-            shell_task.context.mepc = shell::shell_entry as u64;
-            
-            let mut mstatus: usize;
-            core::arch::asm!("csrr {}, mstatus", out(reg) mstatus);
-            mstatus &= !(3 << 11); mstatus |= 1 << 7;
-            core::arch::asm!("csrw mstatus, {}", in(reg) mstatus);
-            
-            return &mut shell_task.context;
+        let stval: usize;
+        unsafe { core::arch::asm!("csrr {}, stval", out(reg) stval); }
+
+        // Store Page Fault：可能是寫入 fork 後共享的 COW 頁面，複製完重新執行該指令即可
+        if code == 15 {
+            let current = scheduler.current_task();
+            if current.root_ppn != 0 {
+                let root = unsafe { &mut *((current.root_ppn << 12) as *mut PageTable) };
+                if unsafe { page_table::handle_cow_fault(root, stval, current.shares_address_space()) } {
+                    return;
+                }
+            }
         }
+
+        // Load / Store Page Fault 落在堆疊還沒有配置的部分：配置新的頁面後重新執行該指令
+        if code == 13 || code == 15 {
+            let current = scheduler.current_task();
+            if current.root_ppn != 0 {
+                let root = unsafe { &mut *((current.root_ppn << 12) as *mut PageTable) };
+                if unsafe { vm::grow_stack(root, &current.user_stack, stval) } {
+                    return;
+                }
+            }
+        }
+
+        let sepc = unsafe { (*ctx_ptr).sepc };
+        let current = scheduler.current_task();
+
+        // S-Mode 的例外已經在上面處理了；沒有位址空間的 Task 不應該在 U-Mode 執行
+        if current.root_ppn == 0 {
+            panic!("Kernel fault: {} at {:#x} (sepc={:#x}, pid={})", fault_name(code), stval, sepc, current.id);
+        }
+
+        // 使用者行程出錯：只終止這個行程，父行程會透過 WAIT 拿到訊號形式的 status
+        if current.user_stack.in_guard(stval) {
+            println!("\n[Kernel] PID {} killed by stack overflow at {:#x} (sepc={:#x}, stack limit {} KB)",
+                current.id, stval, sepc, current.user_stack.size / 1024);
+        } else {
+            println!("\n[Kernel] PID {} killed by {} at {:#x} (sepc={:#x})", current.id, fault_name(code), stval, sepc);
+        }
+        scheduler.exit_current(fault_signal(code));
+        task::sched(scheduler);
+        unreachable!("zombie task resumed");
+    }
+}
+
+fn fault_name(code: usize) -> &'static str {
+    match code {
+        0 => "instruction address misaligned",
+        1 => "instruction access fault",
+        2 => "illegal instruction",
+        3 => "breakpoint",
+        4 => "load address misaligned",
+        5 => "load access fault",
+        6 => "store address misaligned",
+        7 => "store access fault",
+        12 => "instruction page fault",
+        13 => "load page fault",
+        15 => "store page fault",
+        _ => "unknown exception",
+    }
+}
+
+// 例外對應到的訊號 (放進 WAIT 的 status)
+fn fault_signal(code: usize) -> i32 {
+    match code {
+        2 => task::SIGILL,
+        3 => task::SIGTRAP,
+        0 | 4 | 6 => task::SIGBUS,
+        _ => task::SIGSEGV,
     }
 }
 
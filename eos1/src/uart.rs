@@ -1,4 +1,5 @@
 use core::fmt;
+use crate::sync::SpinLock;
 
 pub struct Uart {
     base_address: usize,
@@ -58,14 +59,26 @@ impl fmt::Write for Uart {
 // 建立全域的 UART 實例
 pub static mut WRITER: Uart = Uart::new(0x1000_0000);
 
+// 多個 hart 同時 println! 時，一次只讓一個 hart 輸出整行，避免字元交錯
+static PRINT_LOCK: SpinLock<()> = SpinLock::new(());
+
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
+    let _guard = PRINT_LOCK.lock();
     unsafe {
         let writer_ptr = &raw mut WRITER;
         (*writer_ptr).write_fmt(args).unwrap();
     }
 }
 
+// 供 Firmware (M-Mode，不經過 MMU) 直接輸出字元
+pub fn _putchar(c: u8) {
+    unsafe {
+        let writer_ptr = &raw mut WRITER;
+        (*writer_ptr).putc(c);
+    }
+}
+
 // 供核心呼叫的讀取函式
 pub fn _getchar() -> Option<u8> {
     unsafe {
@@ -1,4 +1,6 @@
-use crate::mm::frame::alloc_frame;
+use crate::mm::frame::alloc_contiguous;
+use crate::sync::SpinLock;
+use crate::task::{self, Scheduler, SchedulerGuard, WaitQueue};
 use core::mem::size_of;
 
 // --- VirtIO MMIO 暫存器偏移量 ---
@@ -14,8 +16,13 @@ const QUEUE_NUM_MAX: usize = 0x034;
 const QUEUE_NUM: usize = 0x038;
 const QUEUE_PFN: usize = 0x040;
 const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
 const STATUS: usize = 0x070;
 
+// QEMU virt 上第一個 virtio-mmio 裝置的 PLIC 中斷編號
+pub const IRQ: u32 = 1;
+
 // --- VirtIO 狀態位元 ---
 const STATUS_ACKNOWLEDGE: u32 = 1;
 const STATUS_DRIVER: u32 = 2;
@@ -61,6 +68,9 @@ struct VirtqUsed {
 }
 
 // --- Driver 狀態 ---
+// 裝置只有一個 virtqueue：同一時間只能有一個 hart 送出請求或檢查完成狀態，
+// 以下的 static mut 都只在持有 DISK_LOCK 時存取
+static DISK_LOCK: SpinLock<()> = SpinLock::new(());
 static mut QUEUE_PAGE: usize = 0;
 static mut USED_IDX: u16 = 0;
 
@@ -77,6 +87,17 @@ struct VirtioBlkReq {
     sector: u64,
 }
 
+// --- 非同步讀取 (DISK_READ syscall) ---
+// 同一時間裝置上只有一個請求；發出請求的 Task 在 DISK_WAITERS 上睡眠，直到完成中斷喚醒它
+static mut ASYNC_REQ: VirtioBlkReq = VirtioBlkReq { type_: VIRTIO_BLK_T_IN, reserved: 0, sector: 0 };
+static mut ASYNC_STATUS: u8 = 255;
+static mut ASYNC_BUF: [u8; 512] = [0; 512];
+static mut ASYNC_OWNER: Option<usize> = None; // 發出請求的 PID
+static mut ASYNC_DONE: bool = false;
+
+// 只在持有 SCHEDULER 的鎖時存取
+pub static mut DISK_WAITERS: WaitQueue = WaitQueue::new();
+
 /// 初始化 VirtIO 驅動
 pub fn init() {
     unsafe {
@@ -104,9 +125,8 @@ pub fn init() {
 
         base.add(QUEUE_NUM / 4).write_volatile(32);
 
-        // 分配兩頁以確保空間足夠
-        let page1 = alloc_frame();
-        let _page2 = alloc_frame(); 
+        // 分配兩頁以確保空間足夠 (Legacy 介面要求 queue 在實體記憶體上連續)
+        let page1 = alloc_contiguous(2);
         if page1 == 0 { panic!("VirtIO OOM"); }
         QUEUE_PAGE = page1;
         
@@ -118,62 +138,95 @@ pub fn init() {
     }
 }
 
-/// 讀取磁碟的一個 Sector (512 bytes)
-pub fn read_disk(sector: u64) -> [u8; 512] {
-    let buffer = [0u8; 512];
-    
+/// 把請求 (Header → 512 bytes Buffer → Status) 放進 virtqueue 並通知裝置
+/// device_writes 代表裝置會寫入 buffer (讀取磁碟)
+unsafe fn submit(req: *mut VirtioBlkReq, buf: u64, device_writes: bool, status: *mut u8) {
     unsafe {
         let base = VIRTIO0 as *mut u32;
         let desc_table = QUEUE_PAGE as *mut VirtqDesc;
         let avail_ring = (QUEUE_PAGE + 512) as *mut VirtqAvail;
-        let used_ring = (QUEUE_PAGE + 4096) as *mut VirtqUsed;
-
-        // 1. Request Header
-        static mut REQ: VirtioBlkReq = VirtioBlkReq {
-            type_: VIRTIO_BLK_T_IN,
-            reserved: 0,
-            sector: 0,
-        };
-        REQ.sector = sector;
 
-        // 2. Status Byte
-        static mut STATUS_BYTE: u8 = 255;
-        STATUS_BYTE = 255;
-
-        // 3. Fill Descriptors
-        // Desc 0: Header
-        (*desc_table.add(0)).addr = &raw mut REQ as u64;
+        // Desc 0: Header (Read-only for device)
+        (*desc_table.add(0)).addr = req as u64;
         (*desc_table.add(0)).len = size_of::<VirtioBlkReq>() as u32;
         (*desc_table.add(0)).flags = VRING_DESC_F_NEXT;
         (*desc_table.add(0)).next = 1;
 
-        // Desc 1: Buffer (Write-only for device)
-        (*desc_table.add(1)).addr = buffer.as_ptr() as u64;
+        // Desc 1: Buffer
+        // [關鍵差異] 寫入磁碟時 *不要* 加 VRING_DESC_F_WRITE，因為是裝置要讀資料
+        (*desc_table.add(1)).addr = buf;
         (*desc_table.add(1)).len = 512;
-        (*desc_table.add(1)).flags = VRING_DESC_F_NEXT | VRING_DESC_F_WRITE;
+        (*desc_table.add(1)).flags = if device_writes { VRING_DESC_F_NEXT | VRING_DESC_F_WRITE } else { VRING_DESC_F_NEXT };
         (*desc_table.add(1)).next = 2;
 
         // Desc 2: Status (Write-only for device)
-        (*desc_table.add(2)).addr = &raw mut STATUS_BYTE as u64;
+        (*desc_table.add(2)).addr = status as u64;
         (*desc_table.add(2)).len = 1;
         (*desc_table.add(2)).flags = VRING_DESC_F_WRITE;
         (*desc_table.add(2)).next = 0;
 
-        // 4. Update Available Ring
+        // Update Available Ring
         let idx = (*avail_ring).idx as usize;
         (*avail_ring).ring[idx % 32] = 0; // Head Index
         
         core::arch::asm!("fence");
         (*avail_ring).idx = (*avail_ring).idx.wrapping_add(1);
 
-        // 5. Notify Device
+        // Notify Device
         base.add(QUEUE_NOTIFY / 4).write_volatile(0);
+    }
+}
+
+/// 裝置是否已完成一個新的請求 (若是，同時消耗掉它)
+unsafe fn take_used() -> bool {
+    unsafe {
+        let used_ring = (QUEUE_PAGE + 4096) as *mut VirtqUsed;
+        let used_idx = core::ptr::read_volatile(&raw const (*used_ring).idx);
+        if used_idx == USED_IDX { return false; }
+        USED_IDX = used_idx;
+        true
+    }
+}
+
+/// 同步請求使用前，先等正在進行中的非同步請求完成
+/// (同步路徑在 Trap Handler 中執行，無法睡眠，只能輪詢；等待的 Task 由稍後的完成中斷喚醒)
+unsafe fn drain_async() {
+    unsafe {
+        let owner = ASYNC_OWNER;
+        if owner.is_some() && !ASYNC_DONE {
+            while !take_used() { core::arch::asm!("nop"); }
+            ASYNC_DONE = true;
+        }
+    }
+}
+
+/// 讀取磁碟的一個 Sector (512 bytes)
+pub fn read_disk(sector: u64) -> [u8; 512] {
+    let buffer = [0u8; 512];
+    let _disk = DISK_LOCK.lock();
+    
+    unsafe {
+        drain_async();
+
+        // 1. Request Header
+        static mut REQ: VirtioBlkReq = VirtioBlkReq {
+            type_: VIRTIO_BLK_T_IN,
+            reserved: 0,
+            sector: 0,
+        };
+        REQ.sector = sector;
 
-        // 6. Wait for completion (Spinning)
-        while (*used_ring).idx == USED_IDX {
+        // 2. Status Byte
+        static mut STATUS_BYTE: u8 = 255;
+        STATUS_BYTE = 255;
+
+        // 3. 送出請求 (Buffer 對裝置而言是 Write-only)
+        submit(&raw mut REQ, buffer.as_ptr() as u64, true, &raw mut STATUS_BYTE);
+
+        // 4. Wait for completion (Spinning)
+        while !take_used() {
             core::arch::asm!("nop");
         }
-        USED_IDX = (*used_ring).idx;
     }
 
     buffer
@@ -182,12 +235,10 @@ pub fn read_disk(sector: u64) -> [u8; 512] {
 // [新增] 寫入磁碟的一個 Sector (512 bytes)
 pub fn write_disk(sector: u64, data: &[u8]) {
     if data.len() != 512 { panic!("Write size must be 512 bytes"); }
+    let _disk = DISK_LOCK.lock();
 
     unsafe {
-        let base = VIRTIO0 as *mut u32;
-        let desc_table = QUEUE_PAGE as *mut VirtqDesc;
-        let avail_ring = (QUEUE_PAGE + 512) as *mut VirtqAvail;
-        let used_ring = (QUEUE_PAGE + 4096) as *mut VirtqUsed;
+        drain_async();
 
         // 1. Request Header
         static mut REQ_WRITE: VirtioBlkReq = VirtioBlkReq {
@@ -201,40 +252,73 @@ pub fn write_disk(sector: u64, data: &[u8]) {
         static mut STATUS_BYTE_WRITE: u8 = 255;
         STATUS_BYTE_WRITE = 255;
 
-        // 3. Fill Descriptors
-        // Desc 0: Header (Read-only for device)
-        (*desc_table.add(0)).addr = &raw mut REQ_WRITE as u64;
-        (*desc_table.add(0)).len = size_of::<VirtioBlkReq>() as u32;
-        (*desc_table.add(0)).flags = VRING_DESC_F_NEXT;
-        (*desc_table.add(0)).next = 1;
+        // 3. 送出請求 (Device reads from RAM to Disk)
+        submit(&raw mut REQ_WRITE, data.as_ptr() as u64, false, &raw mut STATUS_BYTE_WRITE);
 
-        // Desc 1: Buffer (Read-only for device -> Device reads from RAM to Disk)
-        // [關鍵差異] 這裡 *不要* 加 VRING_DESC_F_WRITE，因為是裝置要讀資料
-        (*desc_table.add(1)).addr = data.as_ptr() as u64;
-        (*desc_table.add(1)).len = 512;
-        (*desc_table.add(1)).flags = VRING_DESC_F_NEXT; // 沒有 WRITE flag
-        (*desc_table.add(1)).next = 2;
+        // 4. Wait for completion
+        while !take_used() {
+            core::arch::asm!("nop");
+        }
+    }
+}
 
-        // Desc 2: Status (Write-only for device)
-        (*desc_table.add(2)).addr = &raw mut STATUS_BYTE_WRITE as u64;
-        (*desc_table.add(2)).len = 1;
-        (*desc_table.add(2)).flags = VRING_DESC_F_WRITE;
-        (*desc_table.add(2)).next = 0;
+/// 非同步讀取 (給可以睡眠的 syscall 使用)。
+/// 回傳 Some(data) 代表 pid 先前送出的請求已完成；
+/// 回傳 None 代表請求已送出或裝置正忙，呼叫者應在 DISK_WAITERS 上睡眠後重試
+pub fn read_disk_async(scheduler: &mut Scheduler, pid: usize, sector: u64) -> Option<[u8; 512]> {
+    let _disk = DISK_LOCK.lock();
+    unsafe {
+        match ASYNC_OWNER {
+            Some(owner) if owner == pid => {
+                if !ASYNC_DONE { return None; }
+                ASYNC_OWNER = None;
+                ASYNC_DONE = false;
+                // 完成中斷喚醒的其他 Task 可能比 owner 先執行，看到裝置仍被占用又睡回去了；
+                // 釋放裝置時必須再喚醒它們一次，否則沒有人會叫醒它們
+                let waiters = &raw mut DISK_WAITERS;
+                scheduler.wake_all(&mut *waiters);
+                Some(ASYNC_BUF)
+            }
+            Some(_) => None, // 其他 Task 的請求還在進行中
+            None => {
+                ASYNC_OWNER = Some(pid);
+                ASYNC_DONE = false;
+                ASYNC_REQ.sector = sector;
+                ASYNC_STATUS = 255;
+                submit(&raw mut ASYNC_REQ, &raw mut ASYNC_BUF as u64, true, &raw mut ASYNC_STATUS);
+                None
+            }
+        }
+    }
+}
 
-        // 4. Update Available Ring
-        let idx = (*avail_ring).idx as usize;
-        (*avail_ring).ring[idx % 32] = 0; // Head Index
-        
-        core::arch::asm!("fence");
-        (*avail_ring).idx = (*avail_ring).idx.wrapping_add(1);
+/// 以非同步請求讀取一個 Sector：目前的 Task 在 DISK_WAITERS 上睡到完成中斷為止，其他 Task 照常執行。
+/// 返回時重新持有排程器的鎖，呼叫者要重新取得 current_task
+pub fn read_sector(scheduler: &mut SchedulerGuard, sector: u64) -> [u8; 512] {
+    let pid = scheduler.current_task().id;
+    loop {
+        if let Some(data) = read_disk_async(scheduler, pid, sector) { return data; }
+        let waiters = &raw mut DISK_WAITERS;
+        scheduler.block_current(unsafe { &mut *waiters });
+        task::sched_in_place(scheduler);
+    }
+}
 
-        // 5. Notify Device
-        base.add(QUEUE_NOTIFY / 4).write_volatile(0);
+/// 完成中斷：確認中斷並喚醒等待非同步請求的 Task
+pub fn handle_interrupt(scheduler: &mut Scheduler) {
+    let _disk = DISK_LOCK.lock();
+    unsafe {
+        let base = VIRTIO0 as *mut u32;
+        let status = base.add(INTERRUPT_STATUS / 4).read_volatile();
+        base.add(INTERRUPT_ACK / 4).write_volatile(status);
 
-        // 6. Wait for completion
-        while (*used_ring).idx == USED_IDX {
-            core::arch::asm!("nop");
+        let owner = ASYNC_OWNER;
+        if owner.is_some() && !ASYNC_DONE && take_used() {
+            ASYNC_DONE = true;
+        }
+        if ASYNC_DONE {
+            let waiters = &raw mut DISK_WAITERS;
+            scheduler.wake_all(&mut *waiters);
         }
-        USED_IDX = (*used_ring).idx;
     }
 }
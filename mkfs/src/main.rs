@@ -42,6 +42,10 @@ impl Default for DirEntry {
     }
 }
 
+// 每個目錄表都保留 DIR_SECTORS 個連續的 Sector (與核心的 MAX_DIR_SECTORS 相同)，
+// 核心新增檔案時可以把目錄表延伸到其中還沒用到的 Sector。根目錄表是 Sector 1..=9，資料區從 Sector 10 開始
+const DIR_SECTORS: usize = 9;
+
 // 全域變數：追蹤目前寫到哪個 Sector
 static mut CURRENT_SECTOR: u32 = 10;
 
@@ -70,9 +74,6 @@ fn main() -> std::io::Result<()> {
     // 4. 寫入 Root Directory Table (Sector 1)
     // 注意：SimpleFS 規定 Sector 1 是根目錄表
     disk.seek(SeekFrom::Start(512))?;
-    if root_entries_bytes.len() > 512 {
-        panic!("Root directory too large (> 8 files)!");
-    }
     disk.write_all(&root_entries_bytes)?;
 
     println!("Done! Created {}", TARGET_IMG);
@@ -156,8 +157,13 @@ fn process_directory(dir_path: &Path, disk: &mut File) -> std::io::Result<(Vec<u
         table_bytes.extend_from_slice(bytes);
     }
     
-    // 補齊到 512 bytes (一個 Sector) 的倍數 (Optional, 視需求)
-    // 這裡我們不補齊，直接回傳實際大小，讓上層決定怎麼寫
+    // 目錄表可以跨越多個 Sector，核心讀到第一個空的項目就停止：
+    // 一定要留一個空項目當作結尾，並補齊到保留的 DIR_SECTORS 個 Sector (其餘全為 0)
+    table_bytes.extend_from_slice(&[0u8; size_of::<DirEntry>()]);
+    if table_bytes.len() > 512 * DIR_SECTORS {
+        panic!("Directory {} too large (> {} files)!", dir_path.display(), 512 * DIR_SECTORS / size_of::<DirEntry>() - 1);
+    }
+    table_bytes.resize(512 * DIR_SECTORS, 0);
     
     Ok((table_bytes, count))
 }
\ No newline at end of file
@@ -0,0 +1,31 @@
+// 依執行檔決定連結方式：
+// - libulib：共用函式庫 libulib.so (匯出 ulib 的 API，給 user_dyn 中動態連結的程式使用)
+// - 其他：static-pie (核心直接載入並套用 Relocation；動態載入器 ld 本身也是)
+use std::{env, fs};
+
+const SHARED_LIB: &str = "libulib";
+
+fn main() {
+    println!("cargo:rerun-if-changed=linker.ld");
+    println!("cargo:rerun-if-changed=ulib.map");
+    println!("cargo:rerun-if-changed=src/bin");
+
+    // src/main.rs 是和套件同名的執行檔
+    let mut bins = vec![env::var("CARGO_PKG_NAME").unwrap()];
+    for entry in fs::read_dir("src/bin").expect("src/bin") {
+        let path = entry.expect("src/bin").path();
+        if let Some(name) = path.file_stem().and_then(|s| s.to_str()) { bins.push(name.to_string()); }
+    }
+
+    for name in &bins {
+        if name == SHARED_LIB {
+            // ulib 的物件檔只在被參考時才會從 rlib 中取出，--undefined-glob 把所有 ulib_ 符號都拉進來
+            for arg in ["-shared", "-soname=libulib.so", "-Bsymbolic", "--hash-style=sysv",
+                        "--version-script=ulib.map", "--undefined-glob=ulib_*"] {
+                println!("cargo:rustc-link-arg-bin={}={}", name, arg);
+            }
+        } else {
+            println!("cargo:rustc-link-arg-bin={}=-pie", name);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+use ulib::env;
+
+// static 中的函式指標需要 R_RISCV_RELATIVE 修正才能指向實際載入的位置
+fn one() -> i32 { 1 }
+fn two() -> i32 { 2 }
+static TABLE: [fn() -> i32; 2] = [one, two];
+
+// 每次執行時程式與堆疊的位置都不同 (static-pie + ASLR)；加上參數 child 時只印位址
+fn main(args: &[*const u8]) -> i32 {
+    let local = 0u64;
+    println!("[aslr] main  = {:#x}", main as *const () as usize);
+    println!("[aslr] stack = {:#x}", &local as *const u64 as usize);
+    println!("[aslr] entry = {:#x?}", env::getauxval(env::AT_ENTRY));
+
+    let sum: i32 = TABLE.iter().map(|f| f()).sum();
+    println!("[aslr] relocated table: {}", if sum == 3 { "ok" } else { "BROKEN" });
+    if sum != 3 { return 1; }
+
+    if args.len() < 2 {
+        // 再執行一次自己，比較兩次的位址
+        let path = b"aslr\0";
+        let child = b"child\0";
+        let argv = [path.as_ptr(), child.as_ptr(), core::ptr::null()];
+        let pid = ulib::sys_spawn(path.as_ptr(), argv.as_ptr(), env::environ());
+        if pid < 0 {
+            println!("[aslr] spawn failed: {}", ulib::exec_error_message(pid));
+            return 1;
+        }
+        let mut status = 0;
+        ulib::sys_waitpid(pid, &mut status, 0);
+    }
+    0
+}
+entry_point!(main);
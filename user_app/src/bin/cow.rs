@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+// 放在 .data 的全域變數：fork 後父子行程共享同一個 COW 頁面
+static mut SHARED: u64 = 42;
+
+fn main(_args: &[*const u8]) -> i32 {
+    // 用 volatile 存取，確保這個值真的放在 (同樣是 COW 的) 使用者堆疊上
+    let mut on_stack: u64 = 7;
+    let stack_ptr = &raw mut on_stack;
+    println!("[cow] before fork: SHARED = {}, on_stack = {}", unsafe { core::ptr::read_volatile(&raw const SHARED) }, unsafe { stack_ptr.read_volatile() });
+
+    let pid = ulib::sys_fork();
+    if pid < 0 {
+        println!("[cow] fork failed.");
+        return 1;
+    }
+
+    if pid == 0 {
+        // 子行程：寫入會觸發 Store Page Fault，核心複製出私有頁面
+        unsafe { core::ptr::write_volatile(&raw mut SHARED, 200); }
+        unsafe { stack_ptr.write_volatile(2000); }
+        ulib::sys_yield();
+        let value = unsafe { core::ptr::read_volatile(&raw const SHARED) };
+        let local = unsafe { stack_ptr.read_volatile() };
+        println!("[cow] child sees SHARED = {}, on_stack = {}", value, local);
+        return if value == 200 && local == 2000 { 0 } else { 1 };
+    }
+
+    unsafe { core::ptr::write_volatile(&raw mut SHARED, 100); }
+    unsafe { stack_ptr.write_volatile(1000); }
+    ulib::sys_yield();
+
+    let mut status = 0;
+    if ulib::sys_waitpid(pid, &mut status, 0) != pid { status = 1; }
+
+    let value = unsafe { core::ptr::read_volatile(&raw const SHARED) };
+    let local = unsafe { stack_ptr.read_volatile() };
+    println!("[cow] parent sees SHARED = {}, on_stack = {}", value, local);
+
+    if value == 100 && local == 1000 && status == 0 {
+        println!("[cow] PASS");
+        0
+    } else {
+        println!("[cow] FAIL (child exit code = {})", ulib::wexitstatus(status));
+        1
+    }
+}
+entry_point!(main);
@@ -0,0 +1,26 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+use ulib::env;
+
+// 印出核心放在初始堆疊上的參數、環境變數與 auxv
+fn main(args: &[*const u8]) -> i32 {
+    for (i, arg) in args.iter().enumerate() {
+        println!("argv[{}] = {}", i, unsafe { env::c_str(*arg) });
+    }
+    for (key, value) in env::vars() {
+        println!("{}={}", key, value);
+    }
+    println!("PATH is {:?}", env::var("PATH"));
+
+    println!("AT_PAGESZ = {:?}", env::getauxval(env::AT_PAGESZ));
+    println!("AT_ENTRY  = {:#x?}", env::getauxval(env::AT_ENTRY));
+    println!("AT_PHDR   = {:#x?}", env::getauxval(env::AT_PHDR));
+    if let Some(random) = env::getauxval(env::AT_RANDOM) {
+        let bytes = unsafe { core::slice::from_raw_parts(random as *const u8, 16) };
+        println!("AT_RANDOM = {:02x?}", bytes);
+    }
+    0
+}
+entry_point!(main);
@@ -0,0 +1,17 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+// 用 execve 把自己換成另一個程式：PID 不變，例如 `exec exec pid` 會印出同一個 PID 兩次
+fn main(args: &[*const u8]) -> i32 {
+    println!("exec: my PID is {}", ulib::sys_getpid());
+    if args.len() < 2 {
+        println!("Usage: exec <program> [args...]");
+        return 1;
+    }
+    // 核心放在堆疊上的 argv 以 NULL 結尾，所以 args[1..] 可以直接當成新程式的 argv，環境變數原樣傳下去
+    let ret = ulib::sys_execve(args[1], unsafe { args.as_ptr().add(1) }, ulib::env::environ());
+    println!("exec: execve failed: {} ({})", ulib::exec_error_message(ret), ret);
+    1
+}
+entry_point!(main);
@@ -0,0 +1,184 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+const ROUNDS: usize = 200;
+const STEPS: usize = 1000;
+
+/// 把 f0-f31 與 fcsr 設成 seed 決定的值，ecall 讓出 CPU 後再讀回來：
+/// 核心沒有保存/恢復 FP 狀態的話，另一個行程寫入的值會出現在這裡
+fn registers_survive_yield(seed: u64) -> bool {
+    let mut before = [0f64; 32];
+    let mut after = [0f64; 32];
+    for (i, v) in before.iter_mut().enumerate() { *v = (seed * 100 + i as u64) as f64 + 0.25; }
+    // 每個行程使用不同的捨入模式 (frm)，順便確認 fcsr 也有被保存
+    let frm = seed % 5;
+    let frm_after: u64;
+    unsafe {
+        core::arch::asm!(
+            "fsrm {frm}",
+            "fld f0, 0({src})",
+            "fld f1, 8({src})",
+            "fld f2, 16({src})",
+            "fld f3, 24({src})",
+            "fld f4, 32({src})",
+            "fld f5, 40({src})",
+            "fld f6, 48({src})",
+            "fld f7, 56({src})",
+            "fld f8, 64({src})",
+            "fld f9, 72({src})",
+            "fld f10, 80({src})",
+            "fld f11, 88({src})",
+            "fld f12, 96({src})",
+            "fld f13, 104({src})",
+            "fld f14, 112({src})",
+            "fld f15, 120({src})",
+            "fld f16, 128({src})",
+            "fld f17, 136({src})",
+            "fld f18, 144({src})",
+            "fld f19, 152({src})",
+            "fld f20, 160({src})",
+            "fld f21, 168({src})",
+            "fld f22, 176({src})",
+            "fld f23, 184({src})",
+            "fld f24, 192({src})",
+            "fld f25, 200({src})",
+            "fld f26, 208({src})",
+            "fld f27, 216({src})",
+            "fld f28, 224({src})",
+            "fld f29, 232({src})",
+            "fld f30, 240({src})",
+            "fld f31, 248({src})",
+            "ecall",
+            "fsd f0, 0({dst})",
+            "fsd f1, 8({dst})",
+            "fsd f2, 16({dst})",
+            "fsd f3, 24({dst})",
+            "fsd f4, 32({dst})",
+            "fsd f5, 40({dst})",
+            "fsd f6, 48({dst})",
+            "fsd f7, 56({dst})",
+            "fsd f8, 64({dst})",
+            "fsd f9, 72({dst})",
+            "fsd f10, 80({dst})",
+            "fsd f11, 88({dst})",
+            "fsd f12, 96({dst})",
+            "fsd f13, 104({dst})",
+            "fsd f14, 112({dst})",
+            "fsd f15, 120({dst})",
+            "fsd f16, 128({dst})",
+            "fsd f17, 136({dst})",
+            "fsd f18, 144({dst})",
+            "fsd f19, 152({dst})",
+            "fsd f20, 160({dst})",
+            "fsd f21, 168({dst})",
+            "fsd f22, 176({dst})",
+            "fsd f23, 184({dst})",
+            "fsd f24, 192({dst})",
+            "fsd f25, 200({dst})",
+            "fsd f26, 208({dst})",
+            "fsd f27, 216({dst})",
+            "fsd f28, 224({dst})",
+            "fsd f29, 232({dst})",
+            "fsd f30, 240({dst})",
+            "fsd f31, 248({dst})",
+            "frrm {frm_after}",
+            src = in(reg) before.as_ptr(),
+            dst = in(reg) after.as_mut_ptr(),
+            frm = in(reg) frm,
+            frm_after = lateout(reg) frm_after,
+            in("a7") ulib::SYSCALL_YIELD,
+            out("f0") _,
+            out("f1") _,
+            out("f2") _,
+            out("f3") _,
+            out("f4") _,
+            out("f5") _,
+            out("f6") _,
+            out("f7") _,
+            out("f8") _,
+            out("f9") _,
+            out("f10") _,
+            out("f11") _,
+            out("f12") _,
+            out("f13") _,
+            out("f14") _,
+            out("f15") _,
+            out("f16") _,
+            out("f17") _,
+            out("f18") _,
+            out("f19") _,
+            out("f20") _,
+            out("f21") _,
+            out("f22") _,
+            out("f23") _,
+            out("f24") _,
+            out("f25") _,
+            out("f26") _,
+            out("f27") _,
+            out("f28") _,
+            out("f29") _,
+            out("f30") _,
+            out("f31") _,
+        );
+    }
+    before == after && frm_after == frm
+}
+
+/// 浮點數密集的計算：每一步的結果都能以 f64 精確表示，所以可以和公式解直接比較
+fn compute(seed: u64) -> bool {
+    let scale = seed as f64 + 0.5;
+    let mut sum = 0.0f64;
+    let mut n = 0u64;
+    for _ in 0..ROUNDS {
+        for _ in 0..STEPS {
+            sum += n as f64 * scale;
+            n += 1;
+        }
+        // 計算到一半讓出 CPU，另一個行程會在同一個 hart 上使用 FP 暫存器
+        ulib::sys_yield();
+    }
+    let expected = scale * (n * (n - 1) / 2) as f64;
+    if sum != expected {
+        println!("[fp] seed {}: sum = {}, expected {}", seed, sum, expected);
+        return false;
+    }
+    true
+}
+
+fn run(seed: u64) -> bool {
+    let mut ok = true;
+    for _ in 0..50 {
+        if !registers_survive_yield(seed) {
+            println!("[fp] seed {}: FP registers corrupted across yield", seed);
+            ok = false;
+            break;
+        }
+    }
+    compute(seed) && ok
+}
+
+// 用法: fp，父子行程同時做浮點運算，各自檢查自己的結果
+fn main(_args: &[*const u8]) -> i32 {
+    let pid = ulib::sys_fork();
+    if pid < 0 {
+        println!("[fp] fork failed.");
+        return 1;
+    }
+    if pid == 0 {
+        return if run(2) { 0 } else { 1 };
+    }
+
+    let parent_ok = run(1);
+    let mut status = 0;
+    let child_ok = ulib::sys_waitpid(pid, &mut status, 0) == pid && status == 0;
+
+    if parent_ok && child_ok {
+        println!("[fp] PASS");
+        0
+    } else {
+        println!("[fp] FAIL (parent ok = {}, child exit code = {})", parent_ok, ulib::wexitstatus(status));
+        1
+    }
+}
+entry_point!(main);
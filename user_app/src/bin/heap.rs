@@ -0,0 +1,86 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+use ulib::{PROT_READ, PROT_WRITE};
+
+const PAGE: usize = 4096;
+
+// 在子行程中執行 f，回傳它被哪個訊號終止 (正常結束回傳 0)
+fn run_child(f: fn(), addr: usize) -> i32 {
+    unsafe { TARGET = addr; }
+    let pid = ulib::sys_fork();
+    if pid == 0 {
+        f();
+        ulib::sys_exit(0);
+    }
+    let mut status = 0;
+    if pid < 0 || ulib::sys_waitpid(pid, &mut status, 0) != pid { return -1; }
+    if ulib::wifsignaled(status) { ulib::wtermsig(status) } else { 0 }
+}
+
+static mut TARGET: usize = 0;
+
+fn touch() {
+    unsafe { core::ptr::write_volatile(TARGET as *mut u8, 1); }
+}
+
+// sbrk 加大的空間全為 0 且可寫，縮小後的頁面就不能再存取
+fn check_brk() -> bool {
+    let start = ulib::sbrk(0);
+    if start <= 0 { return false; }
+    let start = start as usize;
+    if ulib::sbrk(3 * PAGE as isize) != start as isize { return false; }
+    let heap = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, 3 * PAGE) };
+    if heap.iter().any(|&b| b != 0) { return false; }
+    heap.fill(0x5A);
+    println!("[heap] brk {:#x} -> {:#x}", start, ulib::sys_brk(0));
+
+    // 子行程拿到的是 Heap 的複本 (COW)，寫入不影響父行程
+    if run_child(touch, start) != 0 || heap[0] != 0x5A { return false; }
+
+    if ulib::sbrk(-(2 * PAGE as isize)) != (start + 3 * PAGE) as isize { return false; }
+    if ulib::sys_brk(0) != start + PAGE { return false; }
+    let freed = run_child(touch, start + PAGE);
+    println!("[heap] touch freed heap page: signal {}", freed);
+    // brk 不能移到 Heap 的起點之前
+    freed == ulib::SIGSEGV && ulib::sys_brk(start - PAGE) == start + PAGE
+}
+
+// munmap 可以拆掉區段的一部分，但不能拆掉不是 mmap 配置的記憶體
+fn check_mmap() -> bool {
+    let addr = ulib::sys_mmap(3 * PAGE, PROT_READ | PROT_WRITE);
+    if addr < 0 { return false; }
+    let addr = addr as usize;
+    unsafe { core::ptr::write_bytes(addr as *mut u8, 0xA5, 3 * PAGE); }
+
+    if ulib::sys_munmap(addr + PAGE, PAGE) != 0 { return false; }
+    let hole = run_child(touch, addr + PAGE);
+    let rest = run_child(touch, addr + 2 * PAGE);
+    println!("[heap] mmap {:#x}: hole signal {}, rest signal {}", addr, hole, rest);
+    if hole != ulib::SIGSEGV || rest != 0 { return false; }
+
+    // 已經拆掉的範圍、程式本身與堆疊都不能 munmap
+    let stack = &addr as *const usize as usize & !(PAGE - 1);
+    if ulib::sys_munmap(addr, 2 * PAGE) == 0 || ulib::sys_munmap(main as *const () as usize & !(PAGE - 1), PAGE) == 0
+        || ulib::sys_munmap(stack, PAGE) == 0 {
+        return false;
+    }
+    ulib::sys_munmap(addr, PAGE) == 0 && ulib::sys_munmap(addr + 2 * PAGE, PAGE) == 0
+}
+
+fn main(_args: &[*const u8]) -> i32 {
+    let brk = check_brk();
+    println!("[heap] brk/sbrk: {}", brk);
+    let mmap = check_mmap();
+    println!("[heap] mmap/munmap: {}", mmap);
+
+    if brk && mmap {
+        println!("[heap] PASS");
+        0
+    } else {
+        println!("[heap] FAIL");
+        1
+    }
+}
+entry_point!(main);
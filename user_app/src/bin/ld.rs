@@ -0,0 +1,379 @@
+// /lib/ld.so：動態連結的程式的載入器。核心依程式的 PT_INTERP 把它載入到另一個隨機的位置，並從這裡開始執行：
+// 1. 從 auxv 的 AT_PHDR 找到程式本身的 Program Header 與 Dynamic Section (程式的 Segment 已經由核心映射好)
+// 2. 把 DT_NEEDED 列出的共用函式庫 (在 /lib 之下) 讀進 mmap 的記憶體，依 Segment 的權限 mprotect (W^X)
+// 3. 套用 Relocation：R_RISCV_JUMP_SLOT (PLT 使用的 GOT) 與 R_RISCV_64 依符號名稱在函式庫的 DT_HASH 中查表，
+//    啟動時就全部填好 (不做 Lazy Binding)
+// 4. 還原啟動時的 sp，跳到程式本身的 entry (AT_ENTRY)
+// 載入器自己是 static-pie (由核心套用 Relocation)，靜態連結 ulib，不依賴 libulib.so
+#![no_std]
+#![no_main]
+
+use core::mem::size_of;
+use ulib::env::{self, AT_ENTRY, AT_PHDR, AT_PHNUM};
+use ulib::{println, sys_exit, PROT_EXEC, PROT_READ, PROT_WRITE};
+
+const ELF_MAGIC: [u8; 4] = [0x7f, 0x45, 0x4c, 0x46];
+const ELFCLASS64: u8 = 2;
+const ET_DYN: u16 = 3;
+const EM_RISCV: u16 = 0xF3;
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_PHDR: u32 = 6;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const DT_NULL: i64 = 0;
+const DT_NEEDED: i64 = 1;
+const DT_PLTRELSZ: i64 = 2;
+const DT_HASH: i64 = 4;
+const DT_STRTAB: i64 = 5;
+const DT_SYMTAB: i64 = 6;
+const DT_RELA: i64 = 7;
+const DT_RELASZ: i64 = 8;
+const DT_RELAENT: i64 = 9;
+const DT_PLTREL: i64 = 20;
+const DT_TEXTREL: i64 = 22;
+const DT_JMPREL: i64 = 23;
+const DT_FLAGS: i64 = 30;
+const DF_TEXTREL: usize = 4;
+
+const R_RISCV_NONE: u32 = 0;
+const R_RISCV_64: u32 = 2;
+const R_RISCV_RELATIVE: u32 = 3;
+const R_RISCV_JUMP_SLOT: u32 = 5;
+
+const SHN_UNDEF: u16 = 0;
+const STB_WEAK: u8 = 2;
+
+/// 最多載入的共用函式庫數量
+const MAX_LIBS: usize = 4;
+/// 共用函式庫所在的目錄 (DT_NEEDED 只有檔名)
+const LIB_DIR: &[u8] = b"/lib/";
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ElfHeader {
+    magic: [u8; 4],
+    class: u8,
+    endian: u8,
+    version: u8,
+    os_abi: u8,
+    abi_version: u8,
+    pad: [u8; 7],
+    type_: u16,
+    machine: u16,
+    version2: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ProgramHeader {
+    type_: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Dyn {
+    tag: i64,
+    val: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Rela {
+    offset: u64,
+    info: u64,
+    addend: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Sym {
+    name: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+}
+
+/// 已經放進記憶體的 ELF (程式本身或共用函式庫)：bias 是實際位址與連結時位址的差
+#[derive(Clone, Copy)]
+struct Object {
+    bias: usize,
+    phdrs: &'static [ProgramHeader],
+    dynamic: *const Dyn,
+}
+
+impl Object {
+    fn new(bias: usize, phdrs: &'static [ProgramHeader]) -> Result<Object, &'static str> {
+        let dynamic = phdrs.iter().find(|ph| ph.type_ == PT_DYNAMIC).ok_or("no dynamic section")?;
+        Ok(Object { bias, phdrs, dynamic: bias.wrapping_add(dynamic.vaddr as usize) as *const Dyn })
+    }
+
+    /// Dynamic Section 的每一項 (到 DT_NULL 為止)
+    fn entries(&self) -> impl Iterator<Item = Dyn> + '_ {
+        (0..).map(|i| unsafe { *self.dynamic.add(i) }).take_while(|entry| entry.tag != DT_NULL)
+    }
+
+    fn value(&self, tag: i64) -> Option<usize> {
+        self.entries().find(|entry| entry.tag == tag).map(|entry| entry.val as usize)
+    }
+
+    /// 內容是位址的項目 (DT_STRTAB 等) 要加上 bias
+    fn address(&self, tag: i64) -> Option<usize> {
+        self.value(tag).map(|vaddr| self.bias.wrapping_add(vaddr))
+    }
+
+    /// DT_STRTAB 中以 0 結尾的字串
+    unsafe fn string(&self, offset: usize) -> &'static [u8] {
+        let Some(strtab) = self.address(DT_STRTAB) else { return &[] };
+        let start = (strtab + offset) as *const u8;
+        let mut len = 0;
+        while unsafe { *start.add(len) } != 0 { len += 1; }
+        unsafe { core::slice::from_raw_parts(start, len) }
+    }
+
+    unsafe fn symbol(&self, index: usize) -> Option<Sym> {
+        let symtab = self.address(DT_SYMTAB)? as *const Sym;
+        Some(unsafe { *symtab.add(index) })
+    }
+
+    /// 以 DT_HASH (System V 的 Hash Table) 找出這個物件定義的符號
+    unsafe fn find_symbol(&self, name: &[u8]) -> Option<usize> {
+        let hash = self.address(DT_HASH)? as *const u32;
+        let nbucket = unsafe { *hash } as usize;
+        if nbucket == 0 { return None; }
+        let buckets = unsafe { hash.add(2) };
+        let chains = unsafe { buckets.add(nbucket) };
+
+        let mut index = unsafe { *buckets.add(elf_hash(name) as usize % nbucket) } as usize;
+        while index != 0 {
+            let sym = unsafe { self.symbol(index) }?;
+            if sym.shndx != SHN_UNDEF && unsafe { self.string(sym.name as usize) } == name {
+                return Some(self.bias.wrapping_add(sym.value as usize));
+            }
+            index = unsafe { *chains.add(index) } as usize;
+        }
+        None
+    }
+
+    fn has_textrel(&self) -> bool {
+        self.value(DT_TEXTREL).is_some() || self.value(DT_FLAGS).is_some_and(|flags| flags & DF_TEXTREL != 0)
+    }
+
+    /// 依 Segment 的 flags 設定權限；writable 時唯讀的 Segment 暫時改成可寫 (不可執行) 以便修改其中的 Relocation
+    fn protect(&self, writable: bool) -> Result<(), &'static str> {
+        for ph in self.phdrs.iter().filter(|ph| ph.type_ == PT_LOAD && ph.memsz > 0) {
+            let mut prot = 0;
+            if ph.flags & PF_R != 0 { prot |= PROT_READ; }
+            if ph.flags & PF_W != 0 { prot |= PROT_WRITE; }
+            if ph.flags & PF_X != 0 { prot |= PROT_EXEC; }
+            if writable && ph.flags & PF_W == 0 { prot = PROT_READ | PROT_WRITE; }
+
+            let start = self.bias.wrapping_add(ph.vaddr as usize) & !0xFFF;
+            let end = (self.bias.wrapping_add((ph.vaddr + ph.memsz) as usize) + 4095) & !0xFFF;
+            if ulib::sys_mprotect(start, end - start, prot) != 0 { return Err("mprotect failed"); }
+        }
+        Ok(())
+    }
+}
+
+/// System V ABI 的符號 Hash 函式
+fn elf_hash(name: &[u8]) -> u32 {
+    let mut h: u32 = 0;
+    for &c in name {
+        h = (h << 4).wrapping_add(c as u32);
+        let g = h & 0xF000_0000;
+        if g != 0 { h ^= g >> 24; }
+        h &= !g;
+    }
+    h
+}
+
+/// 依序在每個共用函式庫中找符號 (先載入的優先)
+unsafe fn lookup(libs: &[Object], name: &[u8]) -> Option<usize> {
+    libs.iter().find_map(|lib| unsafe { lib.find_symbol(name) })
+}
+
+/// 套用 obj 的 DT_RELA 與 DT_JMPREL (PLT) 兩個 Relocation Table
+unsafe fn relocate(obj: &Object, libs: &[Object]) -> Result<(), &'static str> {
+    if obj.value(DT_RELAENT).is_some_and(|ent| ent != size_of::<Rela>()) { return Err("bad DT_RELAENT"); }
+    if obj.value(DT_JMPREL).is_some() && obj.value(DT_PLTREL) != Some(DT_RELA as usize) { return Err("PLT relocations are not RELA"); }
+
+    for (table, size) in [(DT_RELA, DT_RELASZ), (DT_JMPREL, DT_PLTRELSZ)] {
+        let Some(start) = obj.address(table) else { continue };
+        let count = obj.value(size).unwrap_or(0) / size_of::<Rela>();
+        let entries = unsafe { core::slice::from_raw_parts(start as *const Rela, count) };
+        for rela in entries {
+            let value = match rela.info as u32 {
+                R_RISCV_NONE => continue,
+                R_RISCV_RELATIVE => obj.bias.wrapping_add(rela.addend as usize),
+                type_ @ (R_RISCV_64 | R_RISCV_JUMP_SLOT) => {
+                    let sym = unsafe { obj.symbol((rela.info >> 32) as usize) }.ok_or("no symbol table")?;
+                    let name = unsafe { obj.string(sym.name as usize) };
+                    // 物件自己定義的符號直接使用 (和 -Bsymbolic 相同)，否則到共用函式庫中找
+                    let addr = if sym.shndx != SHN_UNDEF {
+                        obj.bias.wrapping_add(sym.value as usize)
+                    } else if let Some(addr) = unsafe { lookup(libs, name) } {
+                        addr
+                    } else if sym.info >> 4 == STB_WEAK {
+                        0
+                    } else {
+                        println!("ld.so: undefined symbol {}", core::str::from_utf8(name).unwrap_or("?"));
+                        return Err("cannot resolve symbols");
+                    };
+                    if type_ == R_RISCV_JUMP_SLOT { addr } else { addr.wrapping_add(rela.addend as usize) }
+                }
+                _ => return Err("unsupported relocation"),
+            };
+            let target = obj.bias.wrapping_add(rela.offset as usize) as *mut usize;
+            unsafe { target.write_unaligned(value); }
+        }
+    }
+    Ok(())
+}
+
+/// 讀取 /lib/<name> 並放進 mmap 的記憶體 (先全部可寫，套用 Relocation 之後才由 Object::protect 設定權限)
+unsafe fn load_library(name: &[u8]) -> Result<Object, &'static str> {
+    let mut path_buf = [0u8; 64];
+    let path_len = LIB_DIR.len() + name.len();
+    if path_len > path_buf.len() { return Err("library name too long"); }
+    path_buf[..LIB_DIR.len()].copy_from_slice(LIB_DIR);
+    path_buf[LIB_DIR.len()..path_len].copy_from_slice(name);
+    let path = core::str::from_utf8(&path_buf[..path_len]).map_err(|_| "bad library name")?;
+
+    // 整個檔案先讀進暫存區，複製完 Segment 之後就歸還
+    let len = ulib::sys_file_len(path);
+    if len <= 0 {
+        println!("ld.so: cannot find {}", path);
+        return Err("library not found");
+    }
+    let file = ulib::sys_mmap(len as usize, PROT_READ | PROT_WRITE);
+    if file < 0 { return Err("out of memory"); }
+    let data = unsafe { core::slice::from_raw_parts_mut(file as *mut u8, len as usize) };
+    if ulib::sys_file_read(path, data) != len { return Err("cannot read library"); }
+
+    if data.len() < size_of::<ElfHeader>() { return Err("truncated library"); }
+    let header = unsafe { core::ptr::read_unaligned(data.as_ptr() as *const ElfHeader) };
+    if header.magic != ELF_MAGIC || header.class != ELFCLASS64 || header.type_ != ET_DYN || header.machine != EM_RISCV {
+        return Err("not a RISC-V shared library");
+    }
+    let phoff = header.phoff as usize;
+    let phnum = header.phnum as usize;
+    if header.phentsize as usize != size_of::<ProgramHeader>() || !phoff.is_multiple_of(8)
+        || phoff.checked_add(phnum * size_of::<ProgramHeader>()).is_none_or(|end| end > data.len()) {
+        return Err("bad program headers");
+    }
+    let phdrs: &[ProgramHeader] = unsafe { core::slice::from_raw_parts(data.as_ptr().add(phoff) as *const ProgramHeader, phnum) };
+
+    // 所有 LOAD Segment 放在一段連續的 mmap 記憶體中，彼此的相對位置和連結時相同
+    let loads = || phdrs.iter().filter(|ph| ph.type_ == PT_LOAD && ph.memsz > 0);
+    for ph in loads() {
+        let file_end = ph.offset.checked_add(ph.filesz);
+        if ph.filesz > ph.memsz || file_end.is_none_or(|end| end > data.len() as u64) || ph.vaddr.checked_add(ph.memsz).is_none() {
+            return Err("segment out of bounds");
+        }
+    }
+    let lowest = loads().map(|ph| ph.vaddr as usize & !0xFFF).min().ok_or("no loadable segment")?;
+    let highest = loads().map(|ph| (ph.vaddr + ph.memsz) as usize).max().unwrap_or(lowest);
+    let base = ulib::sys_mmap(highest - lowest, PROT_READ | PROT_WRITE);
+    if base < 0 { return Err("out of memory"); }
+    let bias = (base as usize).wrapping_sub(lowest);
+
+    // mmap 的記憶體全為 0，BSS 不需要另外清除
+    for ph in loads() {
+        let dest = bias.wrapping_add(ph.vaddr as usize) as *mut u8;
+        unsafe { core::ptr::copy_nonoverlapping(data.as_ptr().add(ph.offset as usize), dest, ph.filesz as usize); }
+    }
+
+    // 之後改用載入後的 Program Header Table (通常在第一個 LOAD Segment 裡，和檔案開頭的 Header 放在一起)
+    let table = phoff as u64..(phoff + phnum * size_of::<ProgramHeader>()) as u64;
+    let phdr = loads().find(|ph| ph.offset <= table.start && table.end <= ph.offset + ph.filesz)
+        .map(|ph| bias.wrapping_add((ph.vaddr + (table.start - ph.offset)) as usize)).ok_or("program headers not loaded")?;
+    let phdrs: &'static [ProgramHeader] = unsafe { core::slice::from_raw_parts(phdr as *const ProgramHeader, phnum) };
+    ulib::sys_munmap(file as usize, len as usize);
+    Object::new(bias, phdrs)
+}
+
+/// 載入程式需要的共用函式庫並完成 Relocation，回傳程式的 entry
+unsafe fn load() -> Result<usize, &'static str> {
+    let phdr = env::getauxval(AT_PHDR).ok_or("missing AT_PHDR")?;
+    let phnum = env::getauxval(AT_PHNUM).ok_or("missing AT_PHNUM")?;
+    let entry = env::getauxval(AT_ENTRY).ok_or("missing AT_ENTRY")?;
+    let phdrs: &'static [ProgramHeader] = unsafe { core::slice::from_raw_parts(phdr as *const ProgramHeader, phnum) };
+    // PT_PHDR 記錄 Program Header Table 連結時的位址，和 AT_PHDR 的差就是程式的 bias
+    let self_ph = phdrs.iter().find(|ph| ph.type_ == PT_PHDR).ok_or("no PT_PHDR")?;
+    let program = Object::new(phdr.wrapping_sub(self_ph.vaddr as usize), phdrs)?;
+
+    let mut libs = [program; MAX_LIBS];
+    let mut count = 0;
+    for entry in program.entries().filter(|entry| entry.tag == DT_NEEDED) {
+        if count == MAX_LIBS { return Err("too many libraries"); }
+        libs[count] = unsafe { load_library(program.string(entry.val as usize)) }?;
+        count += 1;
+    }
+    let libs = &libs[..count];
+
+    for lib in libs {
+        unsafe { relocate(lib, libs) }?;
+        lib.protect(false)?;
+    }
+
+    // 程式的 Segment 已經由核心依權限映射；有 TEXTREL 時先暫時把唯讀的 Segment 改成可寫
+    let textrel = program.has_textrel();
+    if textrel { program.protect(true)?; }
+    unsafe { relocate(&program, libs) }?;
+    if textrel { program.protect(false)?; }
+
+    // 共用函式庫的程式碼是以一般的寫入放進記憶體的，執行前要讓指令讀取看到
+    unsafe { core::arch::asm!("fence.i"); }
+    Ok(entry)
+}
+
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+#[unsafe(link_section = ".text.entry")]
+pub extern "C" fn _start() -> ! {
+    core::arch::naked_asm!("mv a0, sp", "tail {}", sym ld_main);
+}
+
+extern "C" fn ld_main(sp: *const usize) -> ! {
+    unsafe { env::init(sp); }
+    match unsafe { load() } {
+        // 還原啟動時的 sp (程式自己的 _start 會再從這裡讀 argc / argv / envp / auxv)，
+        // a0 = 0 代表沒有結束時要呼叫的函式
+        Ok(entry) => unsafe {
+            core::arch::asm!("mv sp, t1", "li a0, 0", "jr t0", in("t0") entry, in("t1") sp, options(noreturn));
+        },
+        Err(msg) => {
+            println!("ld.so: {}", msg);
+            sys_exit(127);
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    println!("ld.so: {}", info);
+    sys_exit(127);
+}
@@ -0,0 +1,11 @@
+// libulib.so：把 ulib 編成共用函式庫 (連結方式見 build.rs)。
+// 內容全部來自 ulib 本身，這裡只需要引入它；動態連結的程式由 /lib/ld.so 在啟動時載入這個函式庫
+#![no_std]
+#![no_main]
+
+extern crate ulib;
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    ulib::sys_exit(-1);
+}
@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use ulib::thread;
+
+const THREADS: usize = 4;
+const STACK_SIZE: usize = 16384;
+static mut STACKS: [[u8; STACK_SIZE]; THREADS] = [[0; STACK_SIZE]; THREADS];
+
+// Vec 從小的 size class 一路成長到 mmap 的大配置
+fn check_vec() -> bool {
+    let mut v = Vec::new();
+    for i in 0..10000u64 { v.push(i); }
+    v.iter().sum::<u64>() == 10000 * 9999 / 2
+}
+
+fn check_string() -> bool {
+    let mut s = String::new();
+    for i in 0..100 { s.push_str(&format!("{},", i)); }
+    s.len() == 290 && s.starts_with("0,1,2,") && s.ends_with("98,99,")
+}
+
+fn check_btree() -> bool {
+    let mut map = BTreeMap::new();
+    for i in 0..500 { map.insert(i * 7 % 500, i); }
+    for i in (0..500).step_by(2) { map.remove(&i); }
+    map.len() == 250 && map.keys().all(|k| k % 2 == 1)
+}
+
+// 釋放的區塊會被重複使用，Heap 不需要再成長
+fn check_reuse() -> bool {
+    let boxes: Vec<Box<[u8; 100]>> = (0..1000).map(|i| Box::new([i as u8; 100])).collect();
+    if boxes.iter().enumerate().any(|(i, b)| b.iter().any(|&x| x != i as u8)) { return false; }
+    drop(boxes);
+    let brk = ulib::sys_brk(0);
+    let boxes: Vec<Box<[u8; 100]>> = (0..1000).map(|_| Box::new([0; 100])).collect();
+    core::hint::black_box(&boxes);
+    ulib::sys_brk(0) == brk
+}
+
+// 多個 Thread 同時配置與釋放
+fn worker(id: usize) -> i32 {
+    for round in 0..50 {
+        let v: Vec<usize> = (0..64).map(|i| i * id + round).collect();
+        let s = format!("{}-{}", id, round);
+        if v.iter().enumerate().any(|(i, &x)| x != i * id + round) || s != format!("{}-{}", id, round) { return 1; }
+    }
+    0
+}
+
+fn check_threads() -> bool {
+    let mut handles = [const { None }; THREADS];
+    for (i, handle) in handles.iter_mut().enumerate() {
+        let stacks = &raw mut STACKS;
+        let stack = unsafe { &mut (*stacks)[i] };
+        *handle = thread::spawn(worker, i + 1, stack);
+        if handle.is_none() { return false; }
+    }
+    handles.into_iter().flatten().all(|handle| handle.join() == Some(0))
+}
+
+// 配置失敗時印出訊息並結束 (panic_handler 以 -1 結束)，而不是停在原地
+fn check_oom() -> bool {
+    let pid = ulib::sys_fork();
+    if pid == 0 {
+        let v: Vec<u8> = core::hint::black_box(Vec::with_capacity(1 << 37));
+        ulib::sys_exit(v.capacity() as i32);
+    }
+    let mut status = 0;
+    if pid < 0 || ulib::sys_waitpid(pid, &mut status, 0) != pid { return false; }
+    ulib::wifexited(status) && ulib::wexitstatus(status) == 255
+}
+
+fn main(_args: &[*const u8]) -> i32 {
+    let results = [
+        ("vec", check_vec()),
+        ("string", check_string()),
+        ("btree", check_btree()),
+        ("reuse", check_reuse()),
+        ("threads", check_threads()),
+        ("oom", check_oom()),
+    ];
+    for (name, ok) in results { println!("[malloc] {}: {}", name, ok); }
+
+    if results.iter().all(|&(_, ok)| ok) {
+        println!("[malloc] PASS");
+        0
+    } else {
+        println!("[malloc] FAIL");
+        1
+    }
+}
+entry_point!(main);
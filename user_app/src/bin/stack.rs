@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+use core::hint::black_box;
+use ulib::env;
+
+// 以自己當子行程測試長參數列：每個參數 100 個位元組，全部超過一頁
+const LONG_ARGS: usize = 48;
+static LONG_ARG: [u8; 101] = {
+    let mut arg = [b'a'; 101];
+    arg[100] = 0;
+    arg
+};
+
+// 每一層使用約 1KB 的堆疊，回傳值依賴每一層的內容，編譯器無法把遞迴最佳化掉
+fn recurse(depth: usize) -> usize {
+    let mut frame = [0u8; 1024];
+    frame[depth % 1024] = depth as u8;
+    let frame = black_box(frame);
+    if depth == 0 { 0 } else { recurse(depth - 1) + frame[depth % 1024] as usize }
+}
+
+fn overflow() {
+    black_box(recurse(black_box(usize::MAX / 2)));
+}
+
+// 在子行程中執行 f，回傳它被哪個訊號終止 (正常結束回傳 0)
+fn run_child(f: fn()) -> i32 {
+    let pid = ulib::sys_fork();
+    if pid == 0 {
+        f();
+        ulib::sys_exit(0);
+    }
+    let mut status = 0;
+    if pid < 0 || ulib::sys_waitpid(pid, &mut status, 0) != pid { return -1; }
+    if ulib::wifsignaled(status) { ulib::wtermsig(status) } else { 0 }
+}
+
+fn main(args: &[*const u8]) -> i32 {
+    if args.len() > 1 {
+        let total: usize = args[1..].iter().map(|&arg| unsafe { env::c_str(arg) }.len()).sum();
+        println!("[stack] child got {} args ({} bytes)", args.len() - 1, total);
+        return if args.len() - 1 == LONG_ARGS && total == LONG_ARGS * 100 { 0 } else { 1 };
+    }
+
+    // 1. 約 32KB 深的遞迴：超過一頁，堆疊由 Page Fault 依需要成長
+    let sum = recurse(32);
+    println!("[stack] 32KB recursion: ok ({})", sum);
+
+    // 2. 參數與字串超過一頁時也要完整傳給新程式
+    let path = b"stack\0";
+    let mut argv = [core::ptr::null(); LONG_ARGS + 2];
+    argv[0] = path.as_ptr();
+    for arg in argv[1..=LONG_ARGS].iter_mut() { *arg = LONG_ARG.as_ptr(); }
+    let pid = ulib::sys_spawn(path.as_ptr(), argv.as_ptr(), env::environ());
+    let mut status = 0;
+    let args_ok = pid > 0 && ulib::sys_waitpid(pid, &mut status, 0) == pid && ulib::wifexited(status) && ulib::wexitstatus(status) == 0;
+    if pid < 0 { println!("[stack] spawn failed: {}", ulib::exec_error_message(pid)); }
+
+    // 3. 無限遞迴碰到 Guard Page：子行程被核心以 stack overflow 終止
+    let signal = run_child(overflow);
+    println!("[stack] overflow: signal {}", signal);
+
+    if args_ok && signal == ulib::SIGSEGV {
+        println!("[stack] PASS");
+        0
+    } else {
+        println!("[stack] FAIL");
+        1
+    }
+}
+entry_point!(main);
@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+use ulib::sync::Mutex;
+use ulib::thread;
+
+const THREADS: usize = 4;
+const INCREMENTS: usize = 2000;
+const STACK_SIZE: usize = 16384;
+
+static COUNTER: Mutex<u64> = Mutex::new(0);
+static mut STACKS: [[u8; STACK_SIZE]; THREADS] = [[0; STACK_SIZE]; THREADS];
+
+// 每個 Thread 都把自己的編號寫進 TLS，最後再確認沒有被其他 Thread 改掉
+fn worker(id: usize) -> i32 {
+    let tls = thread::tls() as *mut usize;
+    unsafe { tls.write_volatile(id); }
+    for i in 0..INCREMENTS {
+        let mut counter = COUNTER.lock();
+        let value = *counter;
+        // 持有鎖的時候讓出 CPU，其他 Thread 一定會遇到競爭而在 futex 上睡眠
+        if i % 100 == 0 { ulib::sys_yield(); }
+        *counter = value + 1;
+    }
+    if unsafe { tls.read_volatile() } == id { 0 } else { 1 }
+}
+
+// 用法: threads，多個 Thread 以 Mutex 保護同一個計數器
+fn main(_args: &[*const u8]) -> i32 {
+    let mut handles = [const { None }; THREADS];
+    for (i, handle) in handles.iter_mut().enumerate() {
+        let stacks = &raw mut STACKS;
+        let stack = unsafe { &mut (*stacks)[i] };
+        *handle = thread::spawn(worker, i + 1, stack);
+        if handle.is_none() {
+            println!("[threads] spawn failed.");
+            return 1;
+        }
+    }
+
+    // Thread 不是子行程：waitpid(-1) 不能回收它們 (只能由 join 指定 tid)
+    let mut status = 0;
+    let mut ok = ulib::sys_waitpid(-1, &mut status, ulib::WNOHANG) == -1;
+    if !ok { println!("[threads] waitpid(-1) reaped a thread"); }
+    for handle in handles.into_iter().flatten() {
+        let tid = handle.tid();
+        if handle.join() != Some(0) {
+            println!("[threads] thread {} failed", tid);
+            ok = false;
+        }
+    }
+
+    // 已經結束但還沒 join 的 Thread 不會阻止 execve (失敗的原因應該是找不到檔案，不是 Busy)
+    let stacks = &raw mut STACKS;
+    if thread::spawn(|_| 0, 0, unsafe { &mut (*stacks)[0] }).is_some() {
+        ulib::sys_sleep_ms(20);
+        let argv = [c"/no-such-program".as_ptr().cast(), core::ptr::null()];
+        let ret = ulib::sys_execve(argv[0], argv.as_ptr(), ulib::env::environ());
+        if ret != -2 {
+            println!("[threads] execve with an exited thread: {} ({})", ulib::exec_error_message(ret), ret);
+            ok = false;
+        }
+    }
+
+    let total = *COUNTER.lock();
+    println!("[threads] counter = {} (expected {})", total, THREADS * INCREMENTS);
+    if ok && total == (THREADS * INCREMENTS) as u64 {
+        println!("[threads] PASS");
+        0
+    } else {
+        println!("[threads] FAIL");
+        1
+    }
+}
+entry_point!(main);
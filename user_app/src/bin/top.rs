@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+use ulib::CpuStat;
+
+const MAX_HARTS: usize = 8;
+
+// 用法: top [次數]，每秒取樣一次各 hart 的 CPU 使用率
+fn main(args: &[*const u8]) -> i32 {
+    let rounds = if args.len() > 1 {
+        let mut n = 0;
+        let mut ptr = args[1];
+        unsafe {
+            while *ptr >= b'0' && *ptr <= b'9' { n = n * 10 + (*ptr - b'0') as usize; ptr = ptr.add(1); }
+        }
+        if n == 0 { 5 } else { n }
+    } else { 5 };
+
+    let mut prev = [CpuStat::default(); MAX_HARTS];
+    let mut harts = 0;
+    while harts < MAX_HARTS && ulib::sys_cpu_stat(harts, &mut prev[harts]) == 0 { harts += 1; }
+
+    for _ in 0..rounds {
+        ulib::sys_sleep_ms(1000);
+        for (hart, prev) in prev.iter_mut().take(harts).enumerate() {
+            let mut now = CpuStat::default();
+            if ulib::sys_cpu_stat(hart, &mut now) < 0 { continue; }
+            let total = now.uptime - prev.uptime;
+            let idle = now.idle - prev.idle;
+            // 兩次取樣之間沒有經過時間時當作閒置
+            let busy = (idle * 100).checked_div(total).map_or(0, |idle_pct| 100 - idle_pct);
+            println!("hart {}: busy {:3}%  idle {:3}%  uptime {}s", hart, busy, 100 - busy, now.uptime / 10_000_000);
+            *prev = now;
+        }
+    }
+    0
+}
+entry_point!(main);
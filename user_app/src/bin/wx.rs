@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+// 核心依照 Program Header 的 flags 映射頁面 (W^X)，BSS 一定是 0
+static mut ZEROED: [u64; 512] = [0; 512];
+static MESSAGE: &str = "read-only";
+
+// 在子行程中執行 f，回傳它被哪個訊號終止 (正常結束回傳 0)
+fn run_child(f: fn()) -> i32 {
+    let pid = ulib::sys_fork();
+    if pid == 0 {
+        f();
+        ulib::sys_exit(0);
+    }
+    let mut status = 0;
+    if pid < 0 || ulib::sys_waitpid(pid, &mut status, 0) != pid { return -1; }
+    if ulib::wifsignaled(status) { ulib::wtermsig(status) } else { 0 }
+}
+
+fn write_text() {
+    // 寫入自己的程式碼：text 是 R-X
+    unsafe { core::ptr::write_volatile(main as *const () as *mut u8, 0); }
+}
+
+fn write_rodata() {
+    unsafe { core::ptr::write_volatile(MESSAGE.as_ptr() as *mut u8, b'!'); }
+}
+
+fn main(_args: &[*const u8]) -> i32 {
+    let zeroed = &raw const ZEROED;
+    let bss_ok = unsafe { (*zeroed).iter().all(|&v| v == 0) };
+    println!("[wx] bss zeroed: {}", bss_ok);
+
+    let text = run_child(write_text);
+    println!("[wx] write to text: signal {}", text);
+    let rodata = run_child(write_rodata);
+    println!("[wx] write to rodata: signal {}", rodata);
+
+    if bss_ok && text == ulib::SIGSEGV && rodata == ulib::SIGSEGV {
+        println!("[wx] PASS");
+        0
+    } else {
+        println!("[wx] FAIL");
+        1
+    }
+}
+entry_point!(main);
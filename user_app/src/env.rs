@@ -0,0 +1,122 @@
+// 程式啟動時核心放在堆疊上的資訊 (System V ABI)：
+// sp → argc, argv[0..argc], NULL, envp[..], NULL, auxv (type, value)..., AT_NULL
+// entry_point! 在呼叫 main 之前以 init 解析一次，之後用 args / var / getauxval 讀取
+use core::ops::Range;
+use core::ptr::null;
+
+// auxv 的類型 (與 Linux 的編號相同)
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7;
+pub const AT_ENTRY: usize = 9;
+pub const AT_RANDOM: usize = 25;
+
+static mut ARGC: usize = 0;
+static mut ARGV: *const *const u8 = null();
+static mut ENVP: *const *const u8 = null();
+static mut AUXV: *const usize = null();
+
+/// 解析初始堆疊，回傳 argv
+///
+/// # Safety
+/// sp 必須是核心進入程式時的 sp (依照 System V ABI 排好的初始堆疊)，而且只能在程式開始時
+/// (建立任何 Thread 之前) 呼叫一次；entry_point! 與動態載入器會負責呼叫
+#[unsafe(export_name = "ulib_env_init")]
+pub unsafe fn init(sp: *const usize) -> &'static [*const u8] {
+    unsafe {
+        let argc = *sp;
+        let argv = sp.add(1) as *const *const u8;
+        let envp = argv.add(argc + 1);
+        let mut end = envp;
+        while !(*end).is_null() { end = end.add(1); }
+        ARGC = argc;
+        ARGV = argv;
+        ENVP = envp;
+        AUXV = end.add(1) as *const usize;
+    }
+    args()
+}
+
+/// 程式的參數 (以 0 結尾的字串指標，args()[0] 是程式名稱)
+#[unsafe(export_name = "ulib_env_args")]
+pub fn args() -> &'static [*const u8] {
+    unsafe { if ARGV.is_null() { &[] } else { core::slice::from_raw_parts(ARGV, ARGC) } }
+}
+
+/// 以 NULL 結尾的環境變數陣列 (可以直接傳給 sys_execve / sys_spawn)
+#[unsafe(export_name = "ulib_env_environ")]
+pub fn environ() -> *const *const u8 {
+    unsafe { ENVP }
+}
+
+/// 把以 0 結尾的字串轉成 &str (不是合法 UTF-8 時回傳空字串)
+///
+/// # Safety
+/// ptr 必須指向以 0 結尾、在程式結束前都不會被修改或釋放的字串 (例如 args() 與 environ() 中的字串)
+#[unsafe(export_name = "ulib_env_c_str")]
+pub unsafe fn c_str(ptr: *const u8) -> &'static str {
+    unsafe {
+        let mut len = 0;
+        while *ptr.add(len) != 0 { len += 1; }
+        core::str::from_utf8(core::slice::from_raw_parts(ptr, len)).unwrap_or("")
+    }
+}
+
+/// 所有環境變數 (KEY=VALUE 拆成 (KEY, VALUE))
+#[unsafe(export_name = "ulib_env_vars")]
+pub fn vars() -> Vars {
+    Vars { next: unsafe { ENVP } }
+}
+
+pub struct Vars {
+    next: *const *const u8,
+}
+
+impl Iterator for Vars {
+    type Item = (&'static str, &'static str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.next.is_null() { return None; }
+            let entry = unsafe { *self.next };
+            if entry.is_null() { return None; }
+            self.next = unsafe { self.next.add(1) };
+            // 沒有 '=' 的項目不是合法的環境變數，略過
+            if let Some(pair) = unsafe { c_str(entry) }.split_once('=') { return Some(pair); }
+        }
+    }
+}
+
+/// 讀取環境變數，不存在時回傳 None
+#[unsafe(export_name = "ulib_env_var")]
+pub fn var(key: &str) -> Option<&'static str> {
+    vars().find(|(k, _)| *k == key).map(|(_, v)| v)
+}
+
+/// 讀取 auxv 中的值，不存在時回傳 None
+#[unsafe(export_name = "ulib_env_getauxval")]
+pub fn getauxval(type_: usize) -> Option<usize> {
+    let mut entry = unsafe { AUXV };
+    if entry.is_null() { return None; }
+    loop {
+        let (key, value) = unsafe { (*entry, *entry.add(1)) };
+        if key == AT_NULL { return None; }
+        if key == type_ { return Some(value); }
+        entry = unsafe { entry.add(2) };
+    }
+}
+
+// 連結器提供的符號：映像檔的開頭 (ELF Header) 與結尾 (BSS 之後)
+unsafe extern "C" {
+    static __ehdr_start: u8;
+    static _end: u8;
+}
+
+/// 這份 ulib 所在的映像檔載入後的位址範圍：靜態連結時是執行檔本身，動態連結時是 libulib.so
+#[unsafe(export_name = "ulib_env_image_range")]
+pub fn image_range() -> Range<usize> {
+    (&raw const __ehdr_start as usize)..(&raw const _end as usize)
+}
@@ -0,0 +1,113 @@
+// 使用者程式的 Heap：以 size class 管理的 #[global_allocator]，讓程式可以使用 alloc (Vec、String、format! 等)
+// 小的配置進位到 2 的次方，從各自的 free list 取得，list 空了才用 sbrk 向核心要一段新的 Heap；
+// 超過 MAX_SMALL 的配置直接以 mmap 取得整頁，釋放時 munmap 歸還給核心。
+// 配置失敗時回傳 null，alloc 的 handle_alloc_error 會 panic ("memory allocation of N bytes failed")，
+// 由 entry_point! 的 panic_handler 印出訊息並結束行程 (#[alloc_error_handler] 在 stable 上不能使用)
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+use crate::sync::Mutex;
+use crate::{sbrk, sys_mmap, sys_munmap, PROT_READ, PROT_WRITE};
+
+const PAGE_SIZE: usize = 4096;
+// size class：16、32、…、2048 bytes (區塊依自己的大小對齊)
+const MIN_CLASS_SHIFT: usize = 4;
+const CLASSES: usize = 8;
+const MAX_SMALL: usize = 1 << (MIN_CLASS_SHIFT + CLASSES - 1);
+/// free list 空了時一次向核心要的大小
+const REFILL_SIZE: usize = 4 * PAGE_SIZE;
+
+/// 每個 size class 的 free list：空閒區塊的前 8 bytes 存放下一個區塊的位址 (0 代表結尾)
+struct FreeLists {
+    heads: [usize; CLASSES],
+}
+
+impl FreeLists {
+    fn push(&mut self, class: usize, addr: usize) {
+        unsafe { (addr as *mut usize).write(self.heads[class]); }
+        self.heads[class] = addr;
+    }
+
+    fn pop(&mut self, class: usize) -> Option<usize> {
+        let addr = self.heads[class];
+        if addr == 0 { return None; }
+        self.heads[class] = unsafe { (addr as *const usize).read() };
+        Some(addr)
+    }
+
+    /// 用 sbrk 加大 Heap 並切成 class 大小的區塊。
+    /// 程式自己也可能用 sbrk 把結尾移到沒有對齊的位置，所以新的一段先補齊到整頁
+    fn refill(&mut self, class: usize) -> bool {
+        let current = sbrk(0);
+        if current < 0 { return false; }
+        let pad = (current as usize).next_multiple_of(PAGE_SIZE) - current as usize;
+        let start = sbrk((pad + REFILL_SIZE) as isize);
+        if start < 0 { return false; }
+
+        let chunk = start as usize + pad;
+        // 倒著放進 list，配置時從低位址開始使用
+        for addr in (chunk..chunk + REFILL_SIZE).step_by(1 << (class + MIN_CLASS_SHIFT)).rev() {
+            self.push(class, addr);
+        }
+        true
+    }
+}
+
+/// 放得下 layout 的 size class；太大的配置回傳 None
+fn class_of(layout: &Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).max(1 << MIN_CLASS_SHIFT);
+    if size > MAX_SMALL { return None; }
+    Some(size.next_power_of_two().trailing_zeros() as usize - MIN_CLASS_SHIFT)
+}
+
+/// 大的配置：mmap 的位址對齊頁面，要求更大的對齊時回傳 null
+fn alloc_large(layout: &Layout) -> *mut u8 {
+    if layout.align() > PAGE_SIZE { return null_mut(); }
+    let addr = sys_mmap(layout.size(), PROT_READ | PROT_WRITE);
+    if addr < 0 { null_mut() } else { addr as *mut u8 }
+}
+
+/// 同一個行程的 Thread 共用 Heap，free list 以 futex 的 Mutex 保護
+struct Allocator {
+    lists: Mutex<FreeLists>,
+}
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator { lists: Mutex::new(FreeLists { heads: [0; CLASSES] }) };
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = class_of(&layout) else { return alloc_large(&layout) };
+        let mut lists = self.lists.lock();
+        if lists.heads[class] == 0 && !lists.refill(class) { return null_mut(); }
+        lists.pop(class).map_or(null_mut(), |addr| addr as *mut u8)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match class_of(&layout) {
+            Some(class) => self.lists.lock().push(class, ptr as usize),
+            None => { sys_munmap(ptr as usize, layout.size()); }
+        }
+    }
+
+    // mmap 與 sbrk 拿到的記憶體全為 0，但 free list 中的區塊可能被用過，只有大的配置可以省略清除
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if class_of(&layout).is_none() { return alloc_large(&layout); }
+        let ptr = unsafe { self.alloc(layout) };
+        if !ptr.is_null() { unsafe { ptr.write_bytes(0, layout.size()); } }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // 仍在同一個 size class 時不需要搬動
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        if class_of(&layout).is_some() && class_of(&layout) == class_of(&new_layout) { return ptr; }
+        let new_ptr = unsafe { self.alloc(new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                core::ptr::copy_nonoverlapping(ptr, new_ptr, core::cmp::min(layout.size(), new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
@@ -1,45 +1,73 @@
 // === FILE: ./user_app/src/lib.rs ===
 #![no_std]
 
+// Heap 由 heap 模組的 #[global_allocator] 提供，程式加上 extern crate alloc 就能使用 Vec、String 等
+extern crate alloc;
+
 use core::fmt;
 
+pub mod thread;
+pub mod sync;
+pub mod env;
+mod heap;
+
 // --- System Call ID ---
 pub const SYSCALL_PUTCHAR: u64 = 1;
 pub const SYSCALL_GETCHAR: u64 = 2;
 pub const SYSCALL_FILE_LEN: u64 = 3;
 pub const SYSCALL_FILE_READ: u64 = 4;
 pub const SYSCALL_FILE_LIST: u64 = 5;
-pub const SYSCALL_EXEC: u64 = 6;
+pub const SYSCALL_SPAWN: u64 = 6;
 pub const SYSCALL_DISK_READ: u64 = 7;
 pub const SYSCALL_FILE_WRITE: u64 = 8;
+pub const SYSCALL_SLEEP_MS: u64 = 10;
+pub const SYSCALL_CPU_STAT: u64 = 11;
+pub const SYSCALL_THREAD_CREATE: u64 = 12;
+pub const SYSCALL_FUTEX: u64 = 98;
 pub const SYSCALL_EXIT: u64 = 93;
+pub const SYSCALL_NANOSLEEP: u64 = 101;
 // [新增]
 pub const SYSCALL_YIELD: u64 = 124;
+pub const SYSCALL_SETPRIORITY: u64 = 140;
+pub const SYSCALL_GETPRIORITY: u64 = 141;
 pub const SYSCALL_GETPID: u64 = 172;
+pub const SYSCALL_FORK: u64 = 220;
+pub const SYSCALL_EXECVE: u64 = 221;
+pub const SYSCALL_BRK: u64 = 214;
+pub const SYSCALL_MUNMAP: u64 = 215;
+pub const SYSCALL_MMAP: u64 = 222;
+pub const SYSCALL_MPROTECT: u64 = 226;
+pub const SYSCALL_WAIT: u64 = 260;
 
 // --- Wrappers ---
+// 每個函式都以固定的 ulib_ 名稱匯出 (不受 Rust 名稱修飾影響)，動態連結的程式 (user_dyn) 經由 libulib.so 的這些符號呼叫
 
+#[unsafe(export_name = "ulib_putchar")]
 pub fn sys_putchar(c: u8) {
     unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_PUTCHAR, in("a0") c); }
 }
 
+#[unsafe(export_name = "ulib_exit")]
 pub fn sys_exit(code: i32) -> ! {
     unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_EXIT, in("a0") code); }
     loop {}
 }
 
+#[unsafe(export_name = "ulib_file_len")]
 pub fn sys_file_len(name: &str) -> isize {
     let mut ret: isize;
     unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_FILE_LEN, in("a0") name.as_ptr(), in("a1") name.len(), lateout("a0") ret); }
     ret
 }
 
+#[unsafe(export_name = "ulib_file_read")]
 pub fn sys_file_read(name: &str, buf: &mut [u8]) -> isize {
     let mut ret: isize;
     unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_FILE_READ, in("a0") name.as_ptr(), in("a1") name.len(), in("a2") buf.as_mut_ptr(), in("a3") buf.len(), lateout("a0") ret); }
     ret
 }
 
+#[unsafe(export_name = "ulib_file_list")]
 pub fn sys_file_list(index: usize, buf: &mut [u8]) -> isize {
     let mut ret: isize;
     unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_FILE_LIST, in("a0") index, in("a1") buf.as_mut_ptr(), in("a2") buf.len(), lateout("a0") ret); }
@@ -47,17 +75,236 @@ pub fn sys_file_list(index: usize, buf: &mut [u8]) -> isize {
 }
 
 // [新增] Yield
+#[unsafe(export_name = "ulib_yield")]
 pub fn sys_yield() {
     unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_YIELD); }
 }
 
 // [新增] GetPID
+#[unsafe(export_name = "ulib_getpid")]
 pub fn sys_getpid() -> usize {
     let mut ret: usize;
     unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_GETPID, lateout("a0") ret); }
     ret
 }
 
+// nanosleep 的時間參數 (與 struct timespec 相同的配置)
+#[repr(C)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+// 睡眠 ms 毫秒 (期間不佔用 CPU)
+#[unsafe(export_name = "ulib_sleep_ms")]
+pub fn sys_sleep_ms(ms: u64) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_SLEEP_MS, in("a0") ms, lateout("a0") ret); }
+    ret
+}
+
+#[unsafe(export_name = "ulib_nanosleep")]
+pub fn sys_nanosleep(req: &TimeSpec) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_NANOSLEEP, in("a0") req as *const TimeSpec, lateout("a0") ret); }
+    ret
+}
+
+// 某個 hart 的開機時間與累計 Idle 時間 (單位：mtime ticks，10MHz)
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct CpuStat {
+    pub uptime: u64,
+    pub idle: u64,
+}
+
+// 讀取 hart 的 CPU 統計，hart 不存在時回傳 -1
+#[unsafe(export_name = "ulib_cpu_stat")]
+pub fn sys_cpu_stat(hart: usize, stat: &mut CpuStat) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_CPU_STAT, in("a0") hart, in("a1") stat as *mut CpuStat, lateout("a0") ret); }
+    ret
+}
+
+// 優先權：0 最高，數字越大越低 (核心共有 3 層 MLFQ 佇列)
+// setpriority 只能修改自己、後代或同一個行程的 Thread，而且使用者行程只能降低優先權；
+// 沒有權限時回傳 EPERM，沒有這個 Task 或優先權超出範圍時回傳 -1
+pub const EPERM: isize = -2;
+
+#[unsafe(export_name = "ulib_setpriority")]
+pub fn sys_setpriority(pid: usize, priority: usize) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_SETPRIORITY, in("a0") pid, in("a1") priority, lateout("a0") ret); }
+    ret
+}
+
+#[unsafe(export_name = "ulib_getpriority")]
+pub fn sys_getpriority(pid: usize) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_GETPRIORITY, in("a0") pid, lateout("a0") ret); }
+    ret
+}
+
+// nice: 把自己的優先權調整 inc (正數代表降低)，回傳新的優先權或 -1 (使用者行程不能提高優先權)
+#[unsafe(export_name = "ulib_nice")]
+pub fn nice(inc: isize) -> isize {
+    let pid = sys_getpid();
+    let current = sys_getpriority(pid);
+    if current < 0 { return -1; }
+    let target = if current + inc < 0 { 0 } else { current + inc };
+    if sys_setpriority(pid, target as usize) < 0 { return -1; }
+    target
+}
+
+// fork: 父行程回傳子行程 PID，子行程回傳 0，失敗回傳 -1
+#[unsafe(export_name = "ulib_fork")]
+pub fn sys_fork() -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_FORK, lateout("a0") ret); }
+    ret
+}
+
+// spawn: 以 path 的程式建立新的子行程，回傳 PID，失敗回傳負的錯誤碼 (見 exec_error_message)
+// path 與每個參數都是以 0 結尾的字串，argv / envp 是以 NULL 結尾的指標陣列 (envp 可以是 NULL)
+#[unsafe(export_name = "ulib_spawn")]
+pub fn sys_spawn(path: *const u8, argv: *const *const u8, envp: *const *const u8) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_SPAWN, in("a0") path, in("a1") argv, in("a2") envp, lateout("a0") ret); }
+    ret
+}
+
+// execve: 以 path 的程式取代目前的行程 (PID 與開啟的檔案不變)，參數與 spawn 相同
+// 成功時不會返回；失敗 (找不到檔案、不是合法的 ELF、還有其他 Thread) 回傳負的錯誤碼
+#[unsafe(export_name = "ulib_execve")]
+pub fn sys_execve(path: *const u8, argv: *const *const u8, envp: *const *const u8) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_EXECVE, in("a0") path, in("a1") argv, in("a2") envp, lateout("a0") ret); }
+    ret
+}
+
+// spawn / execve 失敗時的錯誤碼：對照表與核心 (exec::ExecError) 共用同一個檔案
+mod exec_errors {
+    include!("../../eos1/src/exec_errors.rs");
+}
+
+// 錯誤碼的說明文字 (-1..-5 是一般的錯誤，ELF 本身的錯誤從 -16 開始)
+#[unsafe(export_name = "ulib_exec_error_message")]
+pub fn exec_error_message(code: isize) -> &'static str {
+    exec_errors::exec_error_description(code).unwrap_or("unknown error")
+}
+
+// mmap / mprotect 的 prot 與 flags (與 Linux 相同的編號)
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+// mmap: 配置 len 位元組 (進位到整頁) 內容全為 0 的匿名記憶體，回傳起始位址，失敗回傳 -1
+// 只支援 MAP_PRIVATE | MAP_ANONYMOUS，位址由核心決定；prot 不能同時有 PROT_WRITE 與 PROT_EXEC (W^X)
+#[unsafe(export_name = "ulib_mmap")]
+pub fn sys_mmap(len: usize, prot: usize) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_MMAP, in("a0") 0, in("a1") len, in("a2") prot, in("a3") MAP_PRIVATE | MAP_ANONYMOUS, in("a4") -1isize, in("a5") 0, lateout("a0") ret); }
+    ret
+}
+
+// munmap: 拆掉 mmap 配置的 [addr, addr + len) (addr 必須對齊頁面，可以只拆一部分)，成功回傳 0
+#[unsafe(export_name = "ulib_munmap")]
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_MUNMAP, in("a0") addr, in("a1") len, lateout("a0") ret); }
+    ret
+}
+
+// brk: 把 Heap 的結尾 (program break) 移到 addr，回傳新的結尾；失敗時回傳原本的結尾，addr 為 0 只查詢
+#[unsafe(export_name = "ulib_brk")]
+pub fn sys_brk(addr: usize) -> usize {
+    let mut ret: usize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_BRK, in("a0") addr, lateout("a0") ret); }
+    ret
+}
+
+// sbrk: 把 Heap 加大 inc 個位元組 (負數代表縮小)，回傳原本的結尾 (新空間的起點)，失敗回傳 -1
+#[unsafe(export_name = "ulib_sbrk")]
+pub fn sbrk(inc: isize) -> isize {
+    let current = sys_brk(0);
+    if inc == 0 { return current as isize; }
+    let Some(target) = current.checked_add_signed(inc) else { return -1 };
+    if sys_brk(target) != target { return -1; }
+    current as isize
+}
+
+// mprotect: 修改 [addr, addr + len) 的權限 (addr 必須對齊頁面，每一頁都必須已經映射)，成功回傳 0
+#[unsafe(export_name = "ulib_mprotect")]
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_MPROTECT, in("a0") addr, in("a1") len, in("a2") prot, lateout("a0") ret); }
+    ret
+}
+
+// waitpid 的 options：子行程都還在執行時立即回傳 0
+pub const WNOHANG: u64 = 1;
+
+// waitpid: pid == -1 代表任意子行程
+// 回傳值: >0 (子行程 PID), 0 (WNOHANG 且子行程仍在執行), -1 (沒有符合的子行程)
+#[unsafe(export_name = "ulib_waitpid")]
+pub fn sys_waitpid(pid: isize, status: &mut i32, options: u64) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_WAIT, in("a0") pid, in("a1") status as *mut i32, in("a2") options, lateout("a0") ret); }
+    ret
+}
+
+// thread_create: 與目前行程共用位址空間的新 Thread，從 entry 開始執行 (a0 = arg、sp = stack_top、tp = tls)
+// 回傳 Thread 的 ID (可以用 waitpid 等它結束)，失敗回傳 -1
+#[unsafe(export_name = "ulib_thread_create")]
+pub fn sys_thread_create(entry: usize, stack_top: usize, arg: usize, tls: usize) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_THREAD_CREATE, in("a0") entry, in("a1") stack_top, in("a2") arg, in("a3") tls, lateout("a0") ret); }
+    ret
+}
+
+pub const FUTEX_WAIT: u64 = 0;
+pub const FUTEX_WAKE: u64 = 1;
+
+// futex_wait: *addr 仍等於 val 時睡眠，直到被 futex_wake 喚醒 (回傳 0)；值已經改變則立即回傳 -1
+#[unsafe(export_name = "ulib_futex_wait")]
+pub fn sys_futex_wait(addr: *const u32, val: u32) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_FUTEX, in("a0") addr, in("a1") FUTEX_WAIT, in("a2") val, lateout("a0") ret); }
+    ret
+}
+
+// futex_wake: 喚醒最多 count 個在 addr 上等待的 Thread，回傳喚醒的數量
+#[unsafe(export_name = "ulib_futex_wake")]
+pub fn sys_futex_wake(addr: *const u32, count: usize) -> isize {
+    let mut ret: isize;
+    unsafe { core::arch::asm!("ecall", in("a7") SYSCALL_FUTEX, in("a0") addr, in("a1") FUTEX_WAKE, in("a2") count, lateout("a0") ret); }
+    ret
+}
+
+// wait: 阻塞直到任意一個子行程結束
+#[unsafe(export_name = "ulib_wait")]
+pub fn sys_wait(status: &mut i32) -> isize {
+    sys_waitpid(-1, status, 0)
+}
+
+// --- 解析 wait 的 status ---
+// 正常結束時 exit code 在 bit 8..15；被核心終止 (例如 Page Fault) 時低 7 位元是訊號編號
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGBUS: i32 = 7;
+pub const SIGSEGV: i32 = 11;
+
+#[unsafe(export_name = "ulib_wifexited")]
+pub fn wifexited(status: i32) -> bool { status & 0x7f == 0 }
+#[unsafe(export_name = "ulib_wexitstatus")]
+pub fn wexitstatus(status: i32) -> i32 { (status >> 8) & 0xff }
+#[unsafe(export_name = "ulib_wifsignaled")]
+pub fn wifsignaled(status: i32) -> bool { status & 0x7f != 0 }
+#[unsafe(export_name = "ulib_wtermsig")]
+pub fn wtermsig(status: i32) -> i32 { status & 0x7f }
+
 // --- Println (保持不變) ---
 pub struct Console;
 impl fmt::Write for Console {
@@ -66,6 +313,7 @@ impl fmt::Write for Console {
         Ok(())
     }
 }
+#[unsafe(export_name = "ulib_print")]
 pub fn _print(args: fmt::Arguments) {
     use fmt::Write;
     let mut out = Console;
@@ -81,17 +329,19 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
-// --- Entry Point Macro (保持不變) ---
+// --- Entry Point Macro ---
+// _start 從 sp 取得核心放好的 argc / argv / envp / auxv (見 env 模組)，再呼叫 main(args)
 #[macro_export]
 macro_rules! entry_point {
     ($path:path) => {
+        #[unsafe(naked)]
         #[unsafe(no_mangle)]
         #[unsafe(link_section = ".text.entry")]
-        pub extern "C" fn _start(argc: usize, argv: *const *const u8) -> ! {
-            let args: &[*const u8] = unsafe { 
-                if argv.is_null() { &[] } 
-                else { core::slice::from_raw_parts(argv, argc) }
-            };
+        pub extern "C" fn _start() -> ! {
+            core::arch::naked_asm!("mv a0, sp", "tail {}", sym __ulib_start);
+        }
+        extern "C" fn __ulib_start(sp: *const usize) -> ! {
+            let args = unsafe { $crate::env::init(sp) };
             let code = $path(args);
             $crate::sys_exit(code);
         }
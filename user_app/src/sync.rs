@@ -0,0 +1,55 @@
+// 以 futex 實作的 Mutex：沒有競爭時完全在使用者空間完成，搶不到鎖才進核心睡眠
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::{sys_futex_wait, sys_futex_wake};
+
+// state：0 = 未上鎖、1 = 上鎖且沒有人等待、2 = 上鎖且可能有人在 futex 上等待
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+const CONTENDED: u32 = 2;
+
+pub struct Mutex<T> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+unsafe impl<T: Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Self { state: AtomicU32::new(UNLOCKED), data: UnsafeCell::new(data) }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if self.state.compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            // 標成 CONTENDED 讓解鎖的一方知道要喚醒；換之前是 UNLOCKED 代表搶到了
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                sys_futex_wait(self.state.as_ptr(), CONTENDED);
+            }
+        }
+        MutexGuard { mutex: self }
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T { unsafe { &*self.mutex.data.get() } }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T { unsafe { &mut *self.mutex.data.get() } }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.mutex.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            sys_futex_wake(self.mutex.state.as_ptr(), 1);
+        }
+    }
+}
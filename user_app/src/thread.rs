@@ -0,0 +1,53 @@
+// Thread：與建立者共用位址空間的 Task
+// 堆疊由呼叫者提供 (例如 static 陣列)，最前面 TLS_SIZE bytes 保留給 TLS，tp 指向這塊區域
+use crate::{sys_exit, sys_thread_create, sys_waitpid};
+
+pub const TLS_SIZE: usize = 64;
+
+// 放在 Thread 堆疊頂端，交給 trampoline 的啟動資訊
+#[repr(C)]
+struct Start {
+    f: fn(usize) -> i32,
+    arg: usize,
+}
+
+pub struct JoinHandle {
+    tid: isize,
+}
+
+impl JoinHandle {
+    pub fn tid(&self) -> isize { self.tid }
+
+    /// 等待 Thread 結束，回傳它的結束碼 (f 的回傳值)；失敗回傳 None
+    pub fn join(self) -> Option<i32> {
+        let mut status = 0;
+        if sys_waitpid(self.tid, &mut status, 0) != self.tid { return None; }
+        if crate::wifexited(status) { Some(crate::wexitstatus(status)) } else { None }
+    }
+}
+
+extern "C" fn trampoline(start: *const Start) -> ! {
+    let start = unsafe { start.read() };
+    sys_exit((start.f)(start.arg));
+}
+
+/// 建立 Thread 執行 f(arg)：stack 在 Thread 結束 (join) 前不能被其他人使用
+pub fn spawn(f: fn(usize) -> i32, arg: usize, stack: &'static mut [u8]) -> Option<JoinHandle> {
+    if stack.len() < TLS_SIZE + 256 { return None; }
+    stack[..TLS_SIZE].fill(0);
+    let tls = stack.as_mut_ptr() as usize;
+
+    let top = (tls + stack.len()) & !0xF;
+    let start_ptr = (top - size_of::<Start>()) & !0xF;
+    unsafe { (start_ptr as *mut Start).write(Start { f, arg }); }
+
+    let tid = sys_thread_create(trampoline as *const () as usize, start_ptr, start_ptr, tls);
+    if tid < 0 { None } else { Some(JoinHandle { tid }) }
+}
+
+/// 目前 Thread 的 TLS 區域 (TLS_SIZE bytes)；主 Thread 的 tp 為 0
+pub fn tls() -> *mut u8 {
+    let tp: usize;
+    unsafe { core::arch::asm!("mv {}, tp", out(reg) tp); }
+    tp as *mut u8
+}
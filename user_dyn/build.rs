@@ -0,0 +1,15 @@
+// 連結到 user_app 編出的 libulib.so (先在 user_app 執行 cargo build --release)。
+// src/lib.rs 只有宣告，ulib_ 符號都沒有定義，只能由 libulib.so 提供 (執行檔會有 DT_NEEDED libulib.so)
+use std::{env, fs, path::PathBuf};
+
+fn main() {
+    let lib = PathBuf::from("../user_app/target/riscv64gc-unknown-none-elf/release/libulib");
+    println!("cargo:rerun-if-changed={}", lib.display());
+    println!("cargo:rerun-if-changed=../user_app/linker.ld");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::copy(&lib, out_dir.join("libulib.so"))
+        .expect("libulib.so not found: run `cargo build --release` in user_app first");
+    println!("cargo:rustc-link-search=native={}", out_dir.display());
+    println!("cargo:rustc-link-lib=dylib=ulib");
+}
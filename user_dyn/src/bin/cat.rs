@@ -23,8 +23,15 @@ fn main(args: &[*const u8]) -> i32 {
         return 1;
     }
 
-    let mut buf = [0u8; 512]; 
-    let read_len = ulib::sys_file_read(filename, &mut buf);
+    if f_len == 0 { return 0; }
+    // 依檔案大小配置緩衝區，用完就歸還
+    let buf_addr = ulib::sys_mmap(f_len as usize, ulib::PROT_READ | ulib::PROT_WRITE);
+    if buf_addr < 0 {
+        println!("Out of memory.");
+        return 1;
+    }
+    let buf = unsafe { core::slice::from_raw_parts_mut(buf_addr as *mut u8, f_len as usize) };
+    let read_len = ulib::sys_file_read(filename, buf);
     if read_len > 0 {
         if let Ok(s) = core::str::from_utf8(&buf[0..read_len as usize]) {
             println!("{}", s);
@@ -32,6 +39,7 @@ fn main(args: &[*const u8]) -> i32 {
             println!("(Binary file)");
         }
     }
+    ulib::sys_munmap(buf_addr as usize, f_len as usize);
     0
 }
 entry_point!(main);
\ No newline at end of file
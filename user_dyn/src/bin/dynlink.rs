@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+#[macro_use] extern crate ulib;
+
+use core::ops::Range;
+use ulib::env;
+
+// static 中指向 libulib.so 的函式指標需要動態載入器以 R_RISCV_64 依符號名稱填入
+static GETPID: fn() -> usize = ulib::sys_getpid;
+
+const PT_LOAD: u32 = 1;
+const PT_PHDR: u32 = 6;
+
+#[repr(C)]
+struct ProgramHeader {
+    type_: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+/// 執行檔本身載入後的位址範圍 (由 auxv 的 AT_PHDR / AT_PHNUM 與 PT_PHDR 算出 bias)
+fn program_range() -> Option<Range<usize>> {
+    let phdr = env::getauxval(env::AT_PHDR)?;
+    let phnum = env::getauxval(env::AT_PHNUM)?;
+    let phdrs = unsafe { core::slice::from_raw_parts(phdr as *const ProgramHeader, phnum) };
+    let bias = phdr.wrapping_sub(phdrs.iter().find(|ph| ph.type_ == PT_PHDR)?.vaddr as usize);
+    let loads = || phdrs.iter().filter(|ph| ph.type_ == PT_LOAD);
+    let start = loads().map(|ph| ph.vaddr as usize).min()?;
+    let end = loads().map(|ph| (ph.vaddr + ph.memsz) as usize).max()?;
+    Some(bias.wrapping_add(start)..bias.wrapping_add(end))
+}
+
+// 動態連結：ulib 的函式在 /lib/libulib.so 中 (由 /lib/ld.so 在 main 之前載入並解析符號)
+fn main(_args: &[*const u8]) -> i32 {
+    let base = env::getauxval(env::AT_BASE);
+    let Some(program) = program_range() else {
+        println!("[dynlink] cannot find the program headers");
+        return 1;
+    };
+    let lib = env::image_range();
+    let getpid = GETPID as *const () as usize;
+    println!("[dynlink] ld.so base   = {:#x?}", base);
+    println!("[dynlink] program      = {:#x}..{:#x}", program.start, program.end);
+    println!("[dynlink] libulib.so   = {:#x}..{:#x}", lib.start, lib.end);
+    println!("[dynlink] ulib_getpid  = {:#x}", getpid);
+
+    // 函式指標必須指向 libulib.so，而不是執行檔裡的一份複本 (靜態連結時兩個範圍會相同)
+    let in_lib = lib.contains(&getpid) && !program.contains(&getpid) && !program.contains(&lib.start);
+    // 經由 PLT 呼叫與經由函式指標呼叫，兩者都要到達 libulib.so 中同一個函式
+    let pid = ulib::sys_getpid();
+    let ok = base.is_some() && in_lib && GETPID() == pid;
+    println!("[dynlink] pid {} via PLT and GOT: {}", pid, if ok { "ok" } else { "BROKEN" });
+    if ok { 0 } else { 1 }
+}
+entry_point!(main);
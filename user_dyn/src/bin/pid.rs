@@ -9,8 +9,8 @@ fn main(_args: &[*const u8]) -> i32 {
     
     for i in 1..=3 {
         println!("PID {} is working... round {}", pid, i);
-        // 主動讓出 CPU
-        ulib::sys_yield();
+        // 睡眠 100ms，期間讓出 CPU
+        ulib::sys_sleep_ms(100);
     }
     
     println!("PID {} finished.", pid);
@@ -0,0 +1,164 @@
+// 動態連結的程式使用的 ulib：只有宣告，沒有任何實作。
+// 每個函式都以 link_name 對應到 libulib.so 匯出的 ulib_ 符號，所以執行檔裡只有對它們的參考 (經由 PLT / GOT)，
+// 由 /lib/ld.so 在啟動時解析；ulib 的修正只要更新 /lib/libulib.so 就會套用到每個程式，不需要重新編譯。
+// (直接依賴 user_app 的 rlib 不行：rustc 會把小函式的程式碼複製進執行檔，連結器就不再需要 libulib.so)
+//
+// 宣告必須與 user_app 中的定義一致。函式使用 Rust 的呼叫慣例 (參數有 &str、slice 等)，
+// 所以程式與 libulib.so 必須以同一個版本的 rustc 編譯 (build.sh 會一起重新編譯)
+#![no_std]
+
+use core::fmt;
+
+// --- 常數與結構 (與 user_app 的 ulib 相同) ---
+
+#[repr(C)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct CpuStat {
+    pub uptime: u64,
+    pub idle: u64,
+}
+
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+
+pub const WNOHANG: u64 = 1;
+
+pub const SIGILL: i32 = 4;
+pub const SIGTRAP: i32 = 5;
+pub const SIGBUS: i32 = 7;
+pub const SIGSEGV: i32 = 11;
+
+// --- libulib.so 的函式 ---
+unsafe extern "Rust" {
+    #[link_name = "ulib_putchar"]
+    pub safe fn sys_putchar(c: u8);
+    #[link_name = "ulib_exit"]
+    pub safe fn sys_exit(code: i32) -> !;
+    #[link_name = "ulib_file_len"]
+    pub safe fn sys_file_len(name: &str) -> isize;
+    #[link_name = "ulib_file_read"]
+    pub safe fn sys_file_read(name: &str, buf: &mut [u8]) -> isize;
+    #[link_name = "ulib_file_list"]
+    pub safe fn sys_file_list(index: usize, buf: &mut [u8]) -> isize;
+    #[link_name = "ulib_yield"]
+    pub safe fn sys_yield();
+    #[link_name = "ulib_getpid"]
+    pub safe fn sys_getpid() -> usize;
+    #[link_name = "ulib_sleep_ms"]
+    pub safe fn sys_sleep_ms(ms: u64) -> isize;
+    #[link_name = "ulib_nanosleep"]
+    pub safe fn sys_nanosleep(req: &TimeSpec) -> isize;
+    #[link_name = "ulib_cpu_stat"]
+    pub safe fn sys_cpu_stat(hart: usize, stat: &mut CpuStat) -> isize;
+    #[link_name = "ulib_setpriority"]
+    pub safe fn sys_setpriority(pid: usize, priority: usize) -> isize;
+    #[link_name = "ulib_getpriority"]
+    pub safe fn sys_getpriority(pid: usize) -> isize;
+    #[link_name = "ulib_nice"]
+    pub safe fn nice(inc: isize) -> isize;
+    #[link_name = "ulib_fork"]
+    pub safe fn sys_fork() -> isize;
+    #[link_name = "ulib_spawn"]
+    pub safe fn sys_spawn(path: *const u8, argv: *const *const u8, envp: *const *const u8) -> isize;
+    #[link_name = "ulib_execve"]
+    pub safe fn sys_execve(path: *const u8, argv: *const *const u8, envp: *const *const u8) -> isize;
+    #[link_name = "ulib_exec_error_message"]
+    pub safe fn exec_error_message(code: isize) -> &'static str;
+    #[link_name = "ulib_mmap"]
+    pub safe fn sys_mmap(len: usize, prot: usize) -> isize;
+    #[link_name = "ulib_munmap"]
+    pub safe fn sys_munmap(addr: usize, len: usize) -> isize;
+    #[link_name = "ulib_brk"]
+    pub safe fn sys_brk(addr: usize) -> usize;
+    #[link_name = "ulib_sbrk"]
+    pub safe fn sbrk(inc: isize) -> isize;
+    #[link_name = "ulib_mprotect"]
+    pub safe fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize;
+    #[link_name = "ulib_waitpid"]
+    pub safe fn sys_waitpid(pid: isize, status: &mut i32, options: u64) -> isize;
+    #[link_name = "ulib_wait"]
+    pub safe fn sys_wait(status: &mut i32) -> isize;
+    #[link_name = "ulib_wifexited"]
+    pub safe fn wifexited(status: i32) -> bool;
+    #[link_name = "ulib_wexitstatus"]
+    pub safe fn wexitstatus(status: i32) -> i32;
+    #[link_name = "ulib_wifsignaled"]
+    pub safe fn wifsignaled(status: i32) -> bool;
+    #[link_name = "ulib_wtermsig"]
+    pub safe fn wtermsig(status: i32) -> i32;
+    #[link_name = "ulib_print"]
+    pub safe fn _print(args: fmt::Arguments);
+}
+
+pub mod env {
+    use core::ops::Range;
+
+    pub const AT_PHDR: usize = 3;
+    pub const AT_PHENT: usize = 4;
+    pub const AT_PHNUM: usize = 5;
+    pub const AT_PAGESZ: usize = 6;
+    pub const AT_BASE: usize = 7;
+    pub const AT_ENTRY: usize = 9;
+    pub const AT_RANDOM: usize = 25;
+
+    unsafe extern "Rust" {
+        /// 解析初始堆疊，回傳 argv (由 entry_point! 呼叫，見 user_app 的 env::init)
+        #[link_name = "ulib_env_init"]
+        pub fn init(sp: *const usize) -> &'static [*const u8];
+        #[link_name = "ulib_env_args"]
+        pub safe fn args() -> &'static [*const u8];
+        #[link_name = "ulib_env_environ"]
+        pub safe fn environ() -> *const *const u8;
+        /// ptr 必須指向以 0 結尾、在程式結束前都不會被修改或釋放的字串
+        #[link_name = "ulib_env_c_str"]
+        pub fn c_str(ptr: *const u8) -> &'static str;
+        #[link_name = "ulib_env_var"]
+        pub safe fn var(key: &str) -> Option<&'static str>;
+        #[link_name = "ulib_env_getauxval"]
+        pub safe fn getauxval(type_: usize) -> Option<usize>;
+        /// libulib.so 載入後的位址範圍
+        #[link_name = "ulib_env_image_range"]
+        pub safe fn image_range() -> Range<usize>;
+    }
+}
+
+// --- 巨集 (與 user_app 的 ulib 相同，但經由 libulib.so 呼叫) ---
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::_print(format_args!($($arg)*)));
+}
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! entry_point {
+    ($path:path) => {
+        #[unsafe(naked)]
+        #[unsafe(no_mangle)]
+        #[unsafe(link_section = ".text.entry")]
+        pub extern "C" fn _start() -> ! {
+            core::arch::naked_asm!("mv a0, sp", "tail {}", sym __ulib_start);
+        }
+        extern "C" fn __ulib_start(sp: *const usize) -> ! {
+            let args = unsafe { $crate::env::init(sp) };
+            let code = $path(args);
+            $crate::sys_exit(code);
+        }
+        #[panic_handler]
+        fn panic(info: &core::panic::PanicInfo) -> ! {
+            $crate::println!("\n[User Panic]");
+            $crate::println!("{}", info);
+            $crate::sys_exit(-1);
+        }
+    };
+}